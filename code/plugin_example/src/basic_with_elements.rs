@@ -1,22 +1,41 @@
-use std::fs::File;
-use std::io::BufWriter;
-use std::time::{Duration, SystemTime};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::Sub;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
 
 use alumet::measurement::{
-    MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue,
+    AttributeValue, MeasurementAccumulator, MeasurementBuffer, MeasurementPoint, MeasurementType, Timestamp,
+    WrappedMeasurementValue,
 };
-use alumet::metrics::{MetricId, RawMetricId, TypedMetricId};
+use alumet::metrics::{Metric, MetricId, RawMetricId, TypedMetricId};
 use alumet::pipeline::elements::error::{PollError, TransformError, WriteError};
-use alumet::pipeline::elements::output::OutputContext;
+use alumet::pipeline::elements::output::{AsyncOutputStream, OutputContext};
 use alumet::pipeline::elements::transform::TransformContext;
+use alumet::pipeline::registry::MetricReader;
 use alumet::pipeline::{trigger, Output, Source, Transform};
 use alumet::plugin::rust::{deserialize_config, serialize_config};
 use alumet::plugin::{rust::AlumetPlugin, AlumetPluginStart, ConfigTable};
 use alumet::resources::{Resource, ResourceConsumer};
 use alumet::units::Unit;
-use anyhow::Context;
+use anyhow::{anyhow, Context};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 
+use crate::output::async_sink::AsyncSink;
+use crate::output::csv::CsvOptions;
+use crate::output::jsonl::JsonLinesOptions;
+use crate::output::line_protocol;
+use crate::output::prometheus::{PrometheusConfig, PrometheusOutput};
+use crate::output::rotation::{RotatingFile, RotationConfig};
+use crate::output::socket::SocketConfig;
+use crate::output::Sink;
+use crate::source::ResourceSpec;
+
+mod extras;
+
+use extras::ExtrasConfig;
+
 // ANCHOR: plugin_struct
 pub struct ExamplePlugin {
     config: Config,
@@ -41,7 +60,11 @@ impl AlumetPlugin for ExamplePlugin {
 
     // ANCHOR: plugin_init
     fn init(config: ConfigTable) -> anyhow::Result<Box<Self>> {
-        let config = deserialize_config(config)?;
+        let mut config: Config = deserialize_config(config)?;
+        // Check the config as soon as possible, to report errors early.
+        check_counters(&config.counters)?;
+        config.output.check()?;
+        config.extras.check()?;
         Ok(Box::new(ExamplePlugin { config }))
     }
     // ANCHOR_END: plugin_init
@@ -54,10 +77,11 @@ impl AlumetPlugin for ExamplePlugin {
 
         // ANCHOR: create_source_metric
         // Create a metric for the source.
+        let counter_unit = Unit::Unity;
         let counter_metric = alumet.create_metric::<u64>(
             //                                      ^^^ type
             "example_source_call_counter",                        // name
-            Unit::Unity,                                          // unit
+            counter_unit.clone(),                                 // unit
             "number of times the example source has been called", // description
         )?;
         // ANCHOR_END: create_source_metric
@@ -69,43 +93,87 @@ impl AlumetPlugin for ExamplePlugin {
             "number of times the example source has been called since the previous measurement",
         )?;
         // ANCHOR_END: create_transform_metric
+        // ANCHOR: create_rate_metric
+        // Create a metric for the rate transform.
+        // Its unit is derived from the unit of the counter: for instance, Joules become Watts.
+        let rate_metric = alumet.create_metric::<f64>(
+            "example_source_call_rate",
+            per_second(counter_unit),
+            "number of times the example source has been called per second",
+        )?;
+        // ANCHOR_END: create_rate_metric
 
         // ANCHOR: add_source
-        // Create the source
-        let source = ExampleSource {
-            metric: counter_metric,
-            counter: 0,
-        };
+        for counter in &self.config.counters {
+            // Create the source
+            let source = ExampleSource::new(counter_metric, counter)?;
 
-        // Configure how the source is triggered: the interval depends on the config
-        let trigger = trigger::builder::time_interval(self.config.poll_interval).build()?;
+            // Configure how the source is triggered: the interval depends on the config
+            let trigger = trigger::builder::time_interval(counter.poll_interval).build()?;
 
-        // Add the source to the measurement pipeline
-        alumet.add_source(Box::new(source), trigger);
+            // Add the source to the measurement pipeline
+            alumet.add_source(Box::new(source), trigger);
+        }
         // ANCHOR_END: add_source
 
         // ANCHOR: add_transform
         // Create the transform
-        let transform = ExampleTransform {
+        let transform = ExampleTransform::<u64> {
             counter_metric: counter_metric.untyped_id(),
-            previous_counter: None,
-            diff_metric,
+            previous_counters: HashMap::new(),
+            diff_metric: diff_metric.untyped_id(),
+            on_decrease: self.config.counter_decrease,
         };
 
         // Add the transform to the measurement pipeline
         alumet.add_transform(Box::new(transform));
         // ANCHOR_END: add_transform
 
-        // ANCHOR: add_output
-        // Open the file and writer
-        let writer = BufWriter::new(File::create("alumet-tutorial-output.txt")?);
+        // ANCHOR: add_rate_transform
+        let rate_transform = RateTransform::<u64> {
+            counter_metric: counter_metric.untyped_id(),
+            previous_counters: HashMap::new(),
+            rate_metric: rate_metric.untyped_id(),
+            on_decrease: self.config.counter_decrease,
+        };
+        alumet.add_transform(Box::new(rate_transform));
+        // ANCHOR_END: add_rate_transform
+
+        if self.config.output.asynchronous {
+            // ANCHOR: add_async_output
+            // Use the non-blocking variant of the output, which runs on the async runtime of Alumet
+            let config = self.config.output.clone();
+            alumet.add_async_output_builder(move |ctx, stream| {
+                let metrics = ctx.metrics_reader();
+                Ok(Box::pin(run_async_output(stream, metrics, config)))
+            });
+            // ANCHOR_END: add_async_output
+        } else {
+            // ANCHOR: add_output
+            // Open the file (or socket)
+            let sink = self.config.output.open()?;
+
+            // Create the output
+            let output = ExampleOutput {
+                sink,
+                format: self.config.output.format.clone(),
+                sync_policy: self.config.output.sync,
+                last_sync: Instant::now(),
+            };
 
-        // Create the output
-        let output = ExampleOutput { writer };
+            // Add the output to the measurement pipeline
+            alumet.add_blocking_output(Box::new(output));
+            // ANCHOR_END: add_output
+        }
 
-        // Add the output to the measurement pipeline
-        alumet.add_blocking_output(Box::new(output));
-        // ANCHOR_END: add_output
+        // Optionally, expose the latest measurements to Prometheus
+        if self.config.prometheus.enabled {
+            let prometheus = PrometheusOutput::start(&self.config.prometheus)?;
+            alumet.add_blocking_output(Box::new(prometheus));
+        }
+
+        // Add the sources and outputs that are not described in the tutorial, if they are enabled
+        self.config.extras.start(alumet, counter_metric)?;
 
         // ANCHOR: plugin_start_tail
         Ok(())
@@ -121,10 +189,114 @@ impl AlumetPlugin for ExamplePlugin {
 
 // ANCHOR: config_struct
 #[derive(Serialize, Deserialize)]
+#[serde(default)] // missing options take their default value
 struct Config {
-    /// Time between each activation of the counter source.
+    /// The counter sources to create, each one with its own trigger.
+    counters: Vec<CounterSourceConfig>,
+
+    /// How the diff transform interprets a decrease of the counter.
+    counter_decrease: CounterDecrease,
+
+    /// Where and how the output writes the measurements.
+    output: OutputConfig,
+
+    /// HTTP endpoint for Prometheus.
+    prometheus: PrometheusConfig,
+
+    /// Sources and outputs that are not described in the tutorial, disabled by default.
+    #[serde(flatten)]
+    extras: ExtrasConfig,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct OutputConfig {
+    /// Path of the file to write.
+    path: PathBuf,
+    /// If true, the measurements are appended to the file.
+    /// Otherwise, the file is truncated when the output starts.
+    append: bool,
+    /// When the written data is flushed and synchronized to the disk.
+    sync: SyncPolicy,
+    /// When to rotate the file, and how many old files to keep.
+    rotation: RotationConfig,
+    /// Format of the file.
+    format: OutputFormat,
+    /// If set, the measurements are sent to this socket instead of being written to `path`.
+    socket: Option<SocketConfig>,
+    /// If true, use the asynchronous variant of the output, which relies on non-blocking IO.
+    /// Files are not rotated in this mode: the rotation must be disabled.
+    asynchronous: bool,
+    /// Maximum number of measurement buffers waiting to be written, in asynchronous mode.
+    /// When the queue is full, the output stops receiving new measurements until the writes catch up.
+    queue_capacity: usize,
+}
+
+/// Format of the output file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum OutputFormat {
+    /// Human-readable text, one line per measurement point.
+    Text,
+    /// Comma-separated values, with a header.
+    Csv(CsvOptions),
+    /// One JSON object per line, with the definition of the metric.
+    JsonLines(JsonLinesOptions),
+    /// InfluxDB line protocol.
+    LineProtocol,
+}
+
+/// When to flush and synchronize (fsync) the output file.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum SyncPolicy {
+    /// After each call to `write`: safest, but slowest.
+    EveryWrite,
+    /// At most once every `interval`.
+    Periodic {
+        #[serde(with = "humantime_serde")]
+        interval: Duration,
+    },
+    /// Only when the output stops.
+    OnStop,
+}
+
+impl SyncPolicy {
+    /// Returns true if the file must be synchronized now, knowing that the previous sync happened at `last_sync`.
+    fn is_due(&self, last_sync: Instant) -> bool {
+        match self {
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::Periodic { interval } => last_sync.elapsed() >= *interval,
+            SyncPolicy::OnStop => false,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CounterSourceConfig {
+    /// Name of the source, which must be unique.
+    name: String,
+    /// Time between each activation of the source.
     #[serde(with = "humantime_serde")]
     poll_interval: Duration,
+    /// The resource and consumer of the measurement points.
+    resource: ResourceSpec,
+    consumer: ResourceSpec,
+    /// Attributes of the measurement points.
+    attributes: BTreeMap<String, String>,
+    /// Value of the counter at the first activation.
+    start_value: u64,
+    /// How much the counter increases at each activation.
+    increment: u64,
+}
+
+/// What a decrease of a counter means.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum CounterDecrease {
+    /// The counter has been reset to zero (for instance, because its source has been re-created).
+    Reset,
+    /// The counter has reached `max_value` and wrapped around to zero.
+    Wrap { max_value: u64 },
 }
 // ANCHOR_END: config_struct
 
@@ -132,33 +304,177 @@ struct Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            poll_interval: Duration::from_secs(1),
+            counters: vec![CounterSourceConfig {
+                name: String::from("counter"),
+                poll_interval: Duration::from_secs(1),
+                resource: ResourceSpec::local_machine(),
+                consumer: ResourceSpec::local_machine(),
+                attributes: BTreeMap::new(),
+                start_value: 0,
+                increment: 1,
+            }],
+            counter_decrease: CounterDecrease::Reset,
+            output: OutputConfig {
+                path: PathBuf::from("alumet-tutorial-output.txt"),
+                append: false,
+                sync: SyncPolicy::Periodic {
+                    interval: Duration::from_secs(5),
+                },
+                rotation: RotationConfig::default(),
+                format: OutputFormat::Text,
+                socket: None,
+                asynchronous: false,
+                queue_capacity: 16,
+            },
+            prometheus: PrometheusConfig::default(),
+            extras: ExtrasConfig::default(),
         }
     }
 }
 // ANCHOR_END: config_default_impl
 
+/// Checks the definitions of the counter sources.
+///
+/// Two sources must not produce the same series, otherwise the transforms would mix their values.
+fn check_counters(counters: &[CounterSourceConfig]) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    let mut series = HashMap::new();
+    for c in counters {
+        if !names.insert(&c.name) {
+            return Err(anyhow!("duplicate counter source name: {}", c.name));
+        }
+        if c.poll_interval.is_zero() {
+            return Err(anyhow!("counter source {}: the poll_interval must not be zero", c.name));
+        }
+        c.resource
+            .to_resource()
+            .and_then(|_| c.consumer.to_consumer())
+            .with_context(|| format!("counter source {}", c.name))?;
+        if let Some(other) = series.insert((&c.resource, &c.consumer, &c.attributes), &c.name) {
+            return Err(anyhow!(
+                "counter sources {other} and {} have the same resource, consumer and attributes",
+                c.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ANCHOR: output_config_impl
+impl OutputConfig {
+    /// Checks the output config.
+    fn check(&mut self) -> anyhow::Result<()> {
+        if self.queue_capacity == 0 {
+            return Err(anyhow!("invalid output config: the queue_capacity must not be zero"));
+        }
+        if let OutputFormat::Csv(csv) = &self.format {
+            csv.check()?;
+        }
+        let rotates = self.rotation.max_size.is_some() || self.rotation.max_age.is_some();
+        if self.asynchronous && rotates {
+            return Err(anyhow!(
+                "invalid output config: the asynchronous output does not rotate the file, remove max_size and max_age from the rotation"
+            ));
+        }
+        match &self.socket {
+            Some(socket) => socket.resolve().map(|_| ()),
+            None => self.resolve_path(),
+        }
+    }
+
+    /// Checks the output path and makes it absolute, so that it does not depend on the working directory.
+    fn resolve_path(&mut self) -> anyhow::Result<()> {
+        let path =
+            std::path::absolute(&self.path).with_context(|| format!("invalid output path: {}", self.path.display()))?;
+        if path.is_dir() {
+            return Err(anyhow!("invalid output path {}: it is a directory", path.display()));
+        }
+        let parent = path
+            .parent()
+            .with_context(|| format!("invalid output path {}: no parent directory", path.display()))?;
+        if !parent.is_dir() {
+            return Err(anyhow!(
+                "invalid output path {}: directory {} does not exist",
+                path.display(),
+                parent.display()
+            ));
+        }
+        self.path = path;
+        Ok(())
+    }
+
+    /// Returns the header to write at the beginning of each file, or when connecting to the socket.
+    fn header(&self) -> String {
+        match &self.format {
+            OutputFormat::Csv(csv) => csv.header(),
+            _ => String::new(),
+        }
+    }
+
+    /// Opens the output file, in append or truncate mode depending on the config, or connects to the socket.
+    fn open(&self) -> anyhow::Result<Sink> {
+        if let Some(socket) = &self.socket {
+            let mut writer = socket.connect()?;
+            let header = self.header();
+            if !header.is_empty() {
+                writer
+                    .write_record(header.as_bytes())
+                    .with_context(|| format!("failed to send the header to {}", socket.address))?;
+            }
+            return Ok(Sink::Socket(writer));
+        }
+
+        let mut file = RotatingFile::open(&self.path, self.append, self.rotation.clone())
+            .with_context(|| format!("failed to open output file {}", self.path.display()))?;
+        // Every new file starts with the header (if any), even after a rotation.
+        file.set_header(self.header());
+        Ok(Sink::File(file))
+    }
+}
+// ANCHOR_END: output_config_impl
+
 // ANCHOR: source
 // ANCHOR: source_struct
 struct ExampleSource {
     metric: TypedMetricId<u64>,
     counter: u64,
+    increment: u64,
+    resource: Resource,
+    consumer: ResourceConsumer,
+    attributes: Vec<(String, String)>,
 }
 // ANCHOR_END: source_struct
 
+impl ExampleSource {
+    fn new(metric: TypedMetricId<u64>, config: &CounterSourceConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            metric,
+            counter: config.start_value,
+            increment: config.increment,
+            resource: config.resource.to_resource()?,
+            consumer: config.consumer.to_consumer()?,
+            attributes: config.attributes.clone().into_iter().collect(),
+        })
+    }
+}
+
 // ANCHOR: source_impl
 impl Source for ExampleSource {
     fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
-        let n_calls = self.counter;
-        self.counter += 1;
+        let value = self.counter;
+        // The counter wraps around on overflow, like a hardware counter.
+        self.counter = self.counter.wrapping_add(self.increment);
 
-        let point = MeasurementPoint::new(
+        let mut point = MeasurementPoint::new(
             timestamp,
             self.metric,
-            Resource::LocalMachine,
-            ResourceConsumer::LocalMachine,
-            n_calls, // measured value
+            self.resource.clone(),
+            self.consumer.clone(),
+            value, // measured value
         );
+        for (key, value) in &self.attributes {
+            point = point.with_attr(key.clone(), AttributeValue::String(value.clone()));
+        }
         acc.push(point);
         Ok(())
     }
@@ -168,103 +484,1088 @@ impl Source for ExampleSource {
 
 // ANCHOR: transform
 // ANCHOR: transform_struct
-struct ExampleTransform {
+/// Computes the difference between consecutive values of a counter,
+/// which can measure integers (`u64`) or floating-point numbers (`f64`).
+struct ExampleTransform<T: CounterValue> {
     counter_metric: RawMetricId,
-    previous_counter: Option<u64>,
-    diff_metric: TypedMetricId<u64>,
+    /// The previous timestamp and value of the counter, for each series.
+    previous_counters: HashMap<SeriesKey, (Timestamp, T)>,
+    diff_metric: RawMetricId,
+    on_decrease: CounterDecrease,
 }
 // ANCHOR_END: transform_struct
 
+// ANCHOR: series_key
+/// Identifies a series of measurement points: all the points that share the same
+/// metric, resource, consumer and attributes belong to the same series.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SeriesKey {
+    metric: RawMetricId,
+    resource: Resource,
+    consumer: ResourceConsumer,
+    attributes: Vec<(String, String)>,
+}
+
+impl SeriesKey {
+    fn of(m: &MeasurementPoint) -> Self {
+        // The order of the attributes does not matter, sort them to get a stable key.
+        let mut attributes: Vec<(String, String)> = m
+            .attributes()
+            .map(|(key, value)| (key.to_owned(), value.to_string()))
+            .collect();
+        attributes.sort();
+        Self {
+            metric: m.metric,
+            resource: m.resource.clone(),
+            consumer: m.consumer.clone(),
+            attributes,
+        }
+    }
+}
+// ANCHOR_END: series_key
+
+// ANCHOR: counter_value
+/// A type of value that can be measured by a counter.
+trait CounterValue: MeasurementType + Copy + PartialOrd + Sub<Output = Self> + Display + Send {
+    /// Extracts the value from a measurement point, if it has the right type.
+    fn from_wrapped(value: &WrappedMeasurementValue) -> Option<Self>;
+
+    /// Wraps the value to store it in a measurement point.
+    fn into_wrapped(self) -> WrappedMeasurementValue;
+
+    /// Converts the value to a floating-point number.
+    fn as_f64(self) -> f64;
+
+    /// Computes the increase of a counter that went from `previous` to `max_value`, then wrapped around to `latest`.
+    ///
+    /// Returns `None` if the values are not compatible with a wraparound.
+    fn wrapped_increase(previous: Self, latest: Self, max_value: u64) -> Option<Self>;
+}
+
+impl CounterValue for u64 {
+    fn from_wrapped(value: &WrappedMeasurementValue) -> Option<Self> {
+        match value {
+            WrappedMeasurementValue::U64(x) => Some(*x),
+            WrappedMeasurementValue::F64(_) => None,
+        }
+    }
+
+    fn into_wrapped(self) -> WrappedMeasurementValue {
+        WrappedMeasurementValue::U64(self)
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn wrapped_increase(previous: Self, latest: Self, max_value: u64) -> Option<Self> {
+        if previous > max_value || latest > max_value {
+            return None;
+        }
+        // The counter went from `previous` to `max_value`, then from 0 to `latest`.
+        // Use u128 to avoid overflows when max_value is close to u64::MAX.
+        let diff = (max_value - previous) as u128 + latest as u128 + 1;
+        Some(u64::try_from(diff).unwrap_or(u64::MAX))
+    }
+}
+
+impl CounterValue for f64 {
+    fn from_wrapped(value: &WrappedMeasurementValue) -> Option<Self> {
+        match value {
+            WrappedMeasurementValue::F64(x) => Some(*x),
+            WrappedMeasurementValue::U64(_) => None,
+        }
+    }
+
+    fn into_wrapped(self) -> WrappedMeasurementValue {
+        WrappedMeasurementValue::F64(self)
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+
+    fn wrapped_increase(previous: Self, latest: Self, max_value: u64) -> Option<Self> {
+        let max_value = max_value as f64;
+        if previous > max_value || latest > max_value {
+            return None;
+        }
+        // Unlike integers, there is no "step" between the maximum and zero.
+        Some((max_value - previous) + latest)
+    }
+}
+// ANCHOR_END: counter_value
+
+// ANCHOR: counter_points
+/// Finds the points of the counter in the buffer, and returns them with their value, sorted by timestamp.
+///
+/// Points that have a value of the wrong type are not returned, but counted in the second element of the tuple.
+fn counter_points<T: CounterValue>(
+    measurements: &MeasurementBuffer,
+    counter_metric: RawMetricId,
+) -> (Vec<(&MeasurementPoint, T)>, usize) {
+    let mut points = Vec::new();
+    let mut n_mismatches = 0;
+    for m in measurements.iter().filter(|m| m.metric == counter_metric) {
+        match T::from_wrapped(&m.value) {
+            Some(value) => points.push((m, value)),
+            None => n_mismatches += 1,
+        }
+    }
+    // The buffer can contain several points of the same series (for instance if the source
+    // has been polled multiple times before the transform was triggered): sort them by timestamp
+    // to process them in the right order.
+    points.sort_by_key(|(m, _)| SystemTime::from(m.timestamp));
+    (points, n_mismatches)
+}
+
+/// Builds the error that reports counter points with a value of the wrong type.
+///
+/// The error is not fatal: the transform can still process the next buffers.
+fn type_mismatch<T: CounterValue>(n_mismatches: usize, counter_metric: RawMetricId) -> TransformError {
+    let expected = std::any::type_name::<T>();
+    log::warn!(
+        "{n_mismatches} point(s) of metric {} ignored: wrong counter type, expected {expected}",
+        counter_metric.as_u64()
+    );
+    TransformError::UnexpectedInput(anyhow!(
+        "wrong counter type for metric {}, expected {expected}",
+        counter_metric.as_u64()
+    ))
+}
+// ANCHOR_END: counter_points
+
 // ANCHOR: transform_impl
-impl Transform for ExampleTransform {
+impl<T: CounterValue> Transform for ExampleTransform<T> {
     fn apply(&mut self, measurements: &mut MeasurementBuffer, _ctx: &TransformContext) -> Result<(), TransformError> {
-        // Find the relevant measurement points and update the last known counter.
-        let mut latest_counter = None;
-        for m in measurements.iter() {
-            if m.metric == self.counter_metric {
-                let value = match m.value {
-                    WrappedMeasurementValue::F64(_) => {
-                        unreachable!("wrong counter type, expected u64")
-                    }
-                    WrappedMeasurementValue::U64(c) => c,
-                };
-                latest_counter = Some((value, m.timestamp));
+        // The context is not needed here: do the work in a separate method, which can be tested without Alumet.
+        self.process(measurements)
+    }
+}
+
+impl<T: CounterValue> ExampleTransform<T> {
+    /// Computes the differences of the counter points in the buffer, and pushes them to the buffer.
+    fn process(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        // Find the relevant measurement points.
+        let (counters, n_mismatches) = counter_points::<T>(measurements, self.counter_metric);
+
+        let mut diffs = Vec::new();
+        for (m, latest) in counters {
+            let key = SeriesKey::of(m);
+            // Out-of-order point: it is older than the state of the series, ignore it.
+            // Otherwise, it would be mistaken for a decrease of the counter.
+            if let Some(&(previous_t, _)) = self.previous_counters.get(&key) {
+                if SystemTime::from(m.timestamp) < SystemTime::from(previous_t) {
+                    log::warn!(
+                        "ignoring an out-of-order counter point: {:?} is older than {:?}",
+                        m.timestamp,
+                        previous_t
+                    );
+                    continue;
+                }
             }
-        }
 
-        // Compute the difference, if we have enough value to do so (previous and latest)
-        if let (Some(previous), Some((latest, t))) = (self.previous_counter, latest_counter) {
-            let diff = latest - previous;
-            // Push the new measurement to the buffer
-            measurements.push(MeasurementPoint::new(
-                t,                              // For convenience, we use the timestamp of the latest counter update
-                self.diff_metric,               // Use the new metric
-                Resource::LocalMachine,         // No specific resource
-                ResourceConsumer::LocalMachine, // No specific consumer
-                diff,                           // The computed value
-            ));
+            // Update the internal state of the series and compute the difference,
+            // if we have enough value to do so (previous and latest).
+            if let Some((_, previous)) = self.previous_counters.insert(key, (m.timestamp, latest)) {
+                let (diff, event) = counter_diff(previous, latest, self.on_decrease);
+
+                // Copy the point to keep its timestamp, resource, consumer and attributes,
+                // then replace the metric and value.
+                let mut diff_point = m.clone();
+                diff_point.metric = self.diff_metric;
+                diff_point.value = diff.into_wrapped();
+
+                // Flag the point if the counter has decreased, so that the users know that something happened.
+                if let Some(event) = event {
+                    log::debug!("counter {event} detected: {previous} -> {latest}");
+                    diff_point = diff_point.with_attr("counter_event", event);
+                }
+                diffs.push(diff_point);
+            }
         }
 
-        // Update the internal state, if possible.
-        // In the case where there are other sources,
-        // the buffer may contain no measurements from our example source.
-        if let Some((latest, _)) = latest_counter {
-            self.previous_counter = Some(latest);
+        // Push the new measurements to the buffer
+        for point in diffs {
+            measurements.push(point);
         }
 
+        // A misconfigured metric should not crash the agent: report the problem with a non-fatal error.
+        if n_mismatches > 0 {
+            return Err(type_mismatch::<T>(n_mismatches, self.counter_metric));
+        }
         Ok(())
     }
 }
 // ANCHOR_END: transform_impl
+
+// ANCHOR: counter_diff
+/// Computes the difference between two consecutive values of a counter.
+///
+/// If the counter has decreased, the difference is computed according to `on_decrease`,
+/// and the kind of event (`"reset"` or `"wrap"`) is returned with the difference.
+/// This function never overflows.
+fn counter_diff<T: CounterValue>(previous: T, latest: T, on_decrease: CounterDecrease) -> (T, Option<&'static str>) {
+    if latest >= previous {
+        return (latest - previous, None);
+    }
+    match on_decrease {
+        CounterDecrease::Wrap { max_value } => match T::wrapped_increase(previous, latest, max_value) {
+            Some(diff) => (diff, Some("wrap")),
+            // A value above the maximum cannot come from a wraparound: the counter has probably been reset.
+            None => (latest, Some("reset")),
+        },
+        // The counter started again from zero.
+        CounterDecrease::Reset => (latest, Some("reset")),
+    }
+}
+// ANCHOR_END: counter_diff
+
+// ANCHOR: rate_transform
+// ANCHOR: rate_transform_struct
+/// Computes the rate of change of a counter, per second, by using the timestamps of its measurement points.
+struct RateTransform<T: CounterValue> {
+    counter_metric: RawMetricId,
+    /// The previous timestamp and value of the counter, for each series.
+    previous_counters: HashMap<SeriesKey, (Timestamp, T)>,
+    rate_metric: RawMetricId,
+    on_decrease: CounterDecrease,
+}
+// ANCHOR_END: rate_transform_struct
+
+// ANCHOR: rate_transform_impl
+impl<T: CounterValue> Transform for RateTransform<T> {
+    fn apply(&mut self, measurements: &mut MeasurementBuffer, _ctx: &TransformContext) -> Result<(), TransformError> {
+        self.process(measurements)
+    }
+}
+
+impl<T: CounterValue> RateTransform<T> {
+    /// Computes the rates of the counter points in the buffer, and pushes them to the buffer.
+    fn process(&mut self, measurements: &mut MeasurementBuffer) -> Result<(), TransformError> {
+        let (counters, n_mismatches) = counter_points::<T>(measurements, self.counter_metric);
+
+        let mut rates = Vec::new();
+        for (m, latest) in counters {
+            let key = SeriesKey::of(m);
+            let Some(&(previous_t, previous)) = self.previous_counters.get(&key) else {
+                // First point of the series, we cannot compute a rate yet.
+                self.previous_counters.insert(key, (m.timestamp, latest));
+                continue;
+            };
+
+            // Compute the time elapsed since the previous point of the series.
+            let elapsed = match SystemTime::from(m.timestamp).duration_since(SystemTime::from(previous_t)) {
+                Ok(elapsed) if elapsed.is_zero() => {
+                    // Duplicate timestamp: the rate would be infinite, ignore the point and keep the first one.
+                    log::debug!("ignoring a counter point with a duplicate timestamp: {:?}", m.timestamp);
+                    continue;
+                }
+                Ok(elapsed) => elapsed,
+                Err(_) => {
+                    // Out-of-order point: it is older than the state of the series, ignore it.
+                    log::warn!(
+                        "ignoring an out-of-order counter point: {:?} is older than {:?}",
+                        m.timestamp,
+                        previous_t
+                    );
+                    continue;
+                }
+            };
+
+            let (diff, event) = counter_diff(previous, latest, self.on_decrease);
+            let rate = diff.as_f64() / elapsed.as_secs_f64();
+            self.previous_counters.insert(key, (m.timestamp, latest));
+
+            let mut rate_point = m.clone();
+            rate_point.metric = self.rate_metric;
+            rate_point.value = WrappedMeasurementValue::F64(rate);
+            if let Some(event) = event {
+                rate_point = rate_point.with_attr("counter_event", event);
+            }
+            rates.push(rate_point);
+        }
+
+        for point in rates {
+            measurements.push(point);
+        }
+
+        if n_mismatches > 0 {
+            return Err(type_mismatch::<T>(n_mismatches, self.counter_metric));
+        }
+        Ok(())
+    }
+}
+// ANCHOR_END: rate_transform_impl
+
+// ANCHOR: per_second
+/// Returns the unit of the rate of change (per second) of a quantity measured in `unit`.
+fn per_second(unit: Unit) -> Unit {
+    match unit {
+        // The rate of change of energy is power: J/s = W.
+        Unit::Joule => Unit::Watt,
+        // Otherwise, build a custom unit such as `1/s`.
+        unit => Unit::Custom {
+            unique_name: format!("{}/s", unit.unique_name()),
+            display_name: format!("{unit}/s"),
+        },
+    }
+}
+// ANCHOR_END: per_second
+// ANCHOR_END: rate_transform
 // ANCHOR_END: transform
 
 // ANCHOR: output
 // ANCHOR: output_struct
 struct ExampleOutput {
-    writer: BufWriter<File>,
+    sink: Sink,
+    format: OutputFormat,
+    sync_policy: SyncPolicy,
+    last_sync: Instant,
 }
 // ANCHOR_END: output_struct
 
 impl Output for ExampleOutput {
     fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
-        use std::io::Write; // necessary for the writeln! macro
+        // The measurement point contains the metric id, but it means nothing to a user.
+        // Use the OutputContext to obtain the metric definition, which contains its name.
+        self.write_with(measurements, |id| ctx.metrics.by_id(id))
+    }
+}
 
+impl ExampleOutput {
+    /// Writes the measurements, with `metric` to find the definition of their metrics.
+    ///
+    /// Unlike `write`, it does not need an `OutputContext`, which makes it easy to test.
+    fn write_with<'a>(
+        &mut self,
+        measurements: &MeasurementBuffer,
+        metric: impl Fn(&RawMetricId) -> Option<&'a Metric>,
+    ) -> Result<(), WriteError> {
         for m in measurements.iter() {
-            // Get a human-readable time from the timestamp.
-            // We later use its Debug implementation to convert it to a string easily.
-            let time = SystemTime::from(m.timestamp);
-
-            // The measurement point contains the metric id, but it means nothing to a user.
-            // Use the OutputContext to obtain the find the metric definition and obtain its name.
-            let metric_name = &ctx
-                .metrics
-                .by_id(&m.metric)
-                .with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?
-                .name;
-
-            // Convert the value to a string. Multiple types are supported, handle them all.
-            let value_str = match m.value {
-                WrappedMeasurementValue::F64(x) => x.to_string(),
-                WrappedMeasurementValue::U64(x) => x.to_string(),
-            };
+            let metric = metric(&m.metric).with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?;
 
-            // The `resource` and `consumer` are each made of two parts: kind and id.
-            let resource_kind = m.resource.kind();
-            let resource_id = m.resource.id_display();
-            let consumer_kind = m.consumer.kind();
-            let consumer_id = m.consumer.id_display();
+            // Convert the point to a line of text, in the configured format.
+            let Some(line) = format_record(&self.format, m, metric)? else {
+                continue;
+            };
 
-            // There can be an arbitrary number of key-value attributes, use `Vec::join` to convert it to a single string.
-            let attributes_str = m
-                .attributes()
-                .map(|(key, value)| format!("{key}='{value}'"))
-                .collect::<Vec<_>>()
-                .join(",");
+            // Write the line to the file.
+            // The line is written at once, so that it cannot be split if the file is rotated.
+            self.sink.write_record(line.as_bytes())?;
+        }
 
-            // Write one line to the file.
-            writeln!(&mut self.writer, "{time:?}: {metric_name} = {value_str}; resource = {resource_kind}/{resource_id}; consumer = {consumer_kind}/{consumer_id}; attributes = [{attributes_str}]")?;
+        // Flush and synchronize the file, if the policy requires it.
+        if self.sync_policy.is_due(self.last_sync) {
+            self.sync()?;
         }
         Ok(())
     }
 }
+
+/// Formats a measurement point as a line of text, in the given format.
+///
+/// Returns `None` if the point cannot be represented in this format.
+fn format_record(format: &OutputFormat, m: &MeasurementPoint, metric: &Metric) -> anyhow::Result<Option<String>> {
+    let metric_name = &metric.name;
+    let line = match format {
+        OutputFormat::Text => text_line(m, metric_name),
+        OutputFormat::Csv(csv) => csv.record(m, metric_name),
+        OutputFormat::JsonLines(jsonl) => jsonl
+            .record(m, metric)
+            .with_context(|| format!("failed to serialize a point of {metric_name} to JSON"))?,
+        OutputFormat::LineProtocol => match line_protocol::record(m, metric_name) {
+            Some(line) => line,
+            None => {
+                log::debug!("skipping a point of {metric_name}: its value is not supported by the line protocol");
+                return Ok(None);
+            }
+        },
+    };
+    Ok(Some(line))
+}
+
+/// Formats a measurement point as a human-readable line of text.
+fn text_line(m: &MeasurementPoint, metric_name: &str) -> String {
+    // Get a human-readable time from the timestamp.
+    // We later use its Debug implementation to convert it to a string easily.
+    let time = SystemTime::from(m.timestamp);
+
+    // Convert the value to a string. Multiple types are supported, handle them all.
+    let value_str = match m.value {
+        WrappedMeasurementValue::F64(x) => x.to_string(),
+        WrappedMeasurementValue::U64(x) => x.to_string(),
+    };
+
+    // The `resource` and `consumer` are each made of two parts: kind and id.
+    let resource_kind = m.resource.kind();
+    let resource_id = m.resource.id_display();
+    let consumer_kind = m.consumer.kind();
+    let consumer_id = m.consumer.id_display();
+
+    // There can be an arbitrary number of key-value attributes, use `Vec::join` to convert it to a single string.
+    let attributes_str = m
+        .attributes()
+        .map(|(key, value)| format!("{key}='{value}'"))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!("{time:?}: {metric_name} = {value_str}; resource = {resource_kind}/{resource_id}; consumer = {consumer_kind}/{consumer_id}; attributes = [{attributes_str}]\n")
+}
 // ANCHOR_END: output
+
+// ANCHOR: output_sync
+impl ExampleOutput {
+    /// Flushes the buffered data to the file, and synchronizes the file to the disk.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sink.sync()?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for ExampleOutput {
+    fn drop(&mut self) {
+        // The output is dropped when it stops: make sure that all the data is written to the disk.
+        if let Err(e) = self.sync() {
+            log::error!("failed to sync the output file on stop: {e}");
+        }
+    }
+}
+// ANCHOR_END: output_sync
+
+// ANCHOR: async_output
+impl OutputConfig {
+    /// Non-blocking variant of [`OutputConfig::open`].
+    async fn open_async(&self) -> anyhow::Result<AsyncSink> {
+        let header = self.header();
+        match &self.socket {
+            Some(socket) => AsyncSink::connect(socket, header.as_bytes()).await,
+            None => AsyncSink::open_file(&self.path, self.append, header.as_bytes())
+                .await
+                .with_context(|| format!("failed to open output file {}", self.path.display())),
+        }
+    }
+}
+
+/// Access to the definitions of the metrics, for the asynchronous output.
+///
+/// In Alumet, it is a [`MetricReader`]. In the tests, it is a simple map.
+trait MetricLookup {
+    /// Formats the points of a buffer, with the definitions of their metrics.
+    async fn format_buffer(
+        &self,
+        format: &OutputFormat,
+        measurements: &MeasurementBuffer,
+    ) -> anyhow::Result<Vec<String>>;
+}
+
+impl MetricLookup for MetricReader {
+    async fn format_buffer(
+        &self,
+        format: &OutputFormat,
+        measurements: &MeasurementBuffer,
+    ) -> anyhow::Result<Vec<String>> {
+        let metrics = self.read().await;
+        format_buffer(format, measurements, |id| metrics.by_id(id))
+    }
+}
+
+/// Formats the points of a buffer, in the same way as the blocking output.
+fn format_buffer<'a>(
+    format: &OutputFormat,
+    measurements: &MeasurementBuffer,
+    metric: impl Fn(&RawMetricId) -> Option<&'a Metric>,
+) -> anyhow::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for m in measurements.iter() {
+        let metric = metric(&m.metric).with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?;
+        if let Some(line) = format_record(format, m, metric)? {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// Asynchronous variant of [`ExampleOutput`], which uses the non-blocking IO of tokio.
+///
+/// The measurements are formatted as they arrive, and written by a separate task.
+/// Both are connected by a bounded queue: when the writes are too slow, the queue fills up and the output
+/// stops pulling measurements from the stream, which applies backpressure to the rest of the pipeline.
+async fn run_async_output(
+    mut stream: AsyncOutputStream,
+    metrics: impl MetricLookup,
+    config: OutputConfig,
+) -> anyhow::Result<()> {
+    let mut sink = config.open_async().await?;
+
+    // Write the data in a separate task.
+    // Each item of the queue contains the lines of a measurement buffer.
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<String>>(config.queue_capacity);
+    let sync_policy = config.sync;
+    let writer = tokio::spawn(async move {
+        let mut last_sync = Instant::now();
+        while let Some(lines) = rx.recv().await {
+            // Like in the blocking output, each line is written at once: over UDP, it is sent in its own datagram.
+            for line in lines {
+                sink.write_record(line.as_bytes()).await?;
+            }
+            if sync_policy.is_due(last_sync) {
+                sink.sync().await?;
+                last_sync = Instant::now();
+            }
+        }
+        // The output stops: make sure that all the data is written to the disk.
+        sink.sync().await
+    });
+
+    // Format the measurements, in the same way as the blocking output.
+    while let Some(measurements) = stream.0.next().await {
+        let measurements = match measurements {
+            Ok(buf) => buf,
+            Err(e) => {
+                log::warn!("some measurements have been lost by the async output: {e:?}");
+                continue;
+            }
+        };
+        let lines = metrics.format_buffer(&config.format, &measurements).await?;
+        // Wait for some room in the queue. If the writer has failed, stop and report its error below.
+        if tx.send(lines).await.is_err() {
+            break;
+        }
+    }
+
+    // Close the queue and wait for the writer to finish.
+    drop(tx);
+    writer.await.context("the writer task has panicked")??;
+    Ok(())
+}
+// ANCHOR_END: async_output
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::time::UNIX_EPOCH;
+
+    use alumet::measurement::WrappedMeasurementType;
+    use alumet::units::PrefixedUnit;
+
+    use crate::output::socket::SocketProtocol;
+
+    use super::*;
+
+    fn counter_metric() -> RawMetricId {
+        RawMetricId::from_u64(0)
+    }
+
+    fn output_metric() -> RawMetricId {
+        RawMetricId::from_u64(1)
+    }
+
+    /// Creates a point of the counter, `t` seconds after the epoch.
+    fn counter_point(t: u64, value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_millis(t * 1000)),
+            counter_metric(),
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    fn buffer(points: Vec<MeasurementPoint>) -> MeasurementBuffer {
+        let mut buffer = MeasurementBuffer::new();
+        for p in points {
+            buffer.push(p);
+        }
+        buffer
+    }
+
+    fn diff_transform<T: CounterValue>(on_decrease: CounterDecrease) -> ExampleTransform<T> {
+        ExampleTransform {
+            counter_metric: counter_metric(),
+            previous_counters: HashMap::new(),
+            diff_metric: output_metric(),
+            on_decrease,
+        }
+    }
+
+    fn rate_transform<T: CounterValue>(on_decrease: CounterDecrease) -> RateTransform<T> {
+        RateTransform {
+            counter_metric: counter_metric(),
+            previous_counters: HashMap::new(),
+            rate_metric: output_metric(),
+            on_decrease,
+        }
+    }
+
+    /// Returns the points computed by a transform: their timestamp (in seconds), value and `counter_event`.
+    fn outputs(buffer: &MeasurementBuffer) -> Vec<(f64, WrappedMeasurementValue, Option<String>)> {
+        buffer
+            .iter()
+            .filter(|m| m.metric == output_metric())
+            .map(|m| {
+                let t = SystemTime::from(m.timestamp).duration_since(UNIX_EPOCH).unwrap();
+                let event = m
+                    .attributes()
+                    .find(|(key, _)| *key == "counter_event")
+                    .map(|(_, value)| value.to_string());
+                (t.as_secs_f64(), m.value.clone(), event)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_of_each_series() {
+        // Four series that differ by their resource, consumer or attributes.
+        let point = |series: u32, t: u64, value: u64| {
+            let mut m = counter_point(t, WrappedMeasurementValue::U64(value));
+            match series {
+                0 => m,
+                1 => {
+                    m.resource = Resource::CpuCore { id: 1 };
+                    m
+                }
+                2 => {
+                    m.consumer = ResourceConsumer::Process { pid: 42 };
+                    m
+                }
+                _ => m.with_attr("counter", "b"),
+            }
+        };
+        let mut transform = diff_transform::<u64>(CounterDecrease::Reset);
+
+        // Both values of each series are in the same buffer.
+        let mut buf = buffer(
+            (0..4)
+                .flat_map(|s| [point(s, 1, 10 * (s as u64 + 1)), point(s, 2, 11 * (s as u64 + 1))])
+                .collect(),
+        );
+        transform.process(&mut buf).unwrap();
+        let diffs: Vec<_> = buf.iter().filter(|m| m.metric == output_metric()).collect();
+        let values: Vec<_> = diffs.iter().map(|m| m.value.clone()).collect();
+        assert_eq!(values, (1..=4).map(WrappedMeasurementValue::U64).collect::<Vec<_>>());
+
+        // The diffs belong to the same series as the counter.
+        assert_eq!(diffs[1].resource, Resource::CpuCore { id: 1 });
+        assert_eq!(diffs[2].consumer, ResourceConsumer::Process { pid: 42 });
+        assert_eq!(diffs[3].attributes().count(), 1);
+    }
+
+    #[test]
+    fn diff_of_several_points_of_a_series_in_one_buffer() {
+        let mut transform = diff_transform::<u64>(CounterDecrease::Reset);
+        // The points are not sorted in the buffer.
+        let mut buf = buffer(vec![
+            counter_point(3, WrappedMeasurementValue::U64(35)),
+            counter_point(1, WrappedMeasurementValue::U64(10)),
+            counter_point(2, WrappedMeasurementValue::U64(20)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![
+                (2.0, WrappedMeasurementValue::U64(10), None),
+                (3.0, WrappedMeasurementValue::U64(15), None),
+            ]
+        );
+
+        // The next buffer continues from the latest point.
+        let mut buf = buffer(vec![counter_point(4, WrappedMeasurementValue::U64(36))]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(outputs(&buf), vec![(4.0, WrappedMeasurementValue::U64(1), None)]);
+    }
+
+    #[test]
+    fn diff_ignores_out_of_order_points() {
+        let mut transform = diff_transform::<u64>(CounterDecrease::Reset);
+        let mut buf = buffer(vec![counter_point(2, WrappedMeasurementValue::U64(20))]);
+        transform.process(&mut buf).unwrap();
+
+        // The point of t=1 is older than the state: it is not a reset of the counter.
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(10)),
+            counter_point(3, WrappedMeasurementValue::U64(30)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(outputs(&buf), vec![(3.0, WrappedMeasurementValue::U64(10), None)]);
+    }
+
+    #[test]
+    fn decrease_is_a_reset() {
+        let mut transform = diff_transform::<u64>(CounterDecrease::Reset);
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(100)),
+            counter_point(2, WrappedMeasurementValue::U64(3)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![(2.0, WrappedMeasurementValue::U64(3), Some(String::from("reset")))]
+        );
+    }
+
+    #[test]
+    fn decrease_is_a_wraparound() {
+        let mut transform = diff_transform::<u64>(CounterDecrease::Wrap { max_value: 127 });
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(120)),
+            // 120 -> 127 -> 0 -> 3
+            counter_point(2, WrappedMeasurementValue::U64(3)),
+            // Above the maximum: this cannot be a wraparound, the counter has been reset.
+            counter_point(3, WrappedMeasurementValue::U64(200)),
+            counter_point(4, WrappedMeasurementValue::U64(2)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![
+                (2.0, WrappedMeasurementValue::U64(11), Some(String::from("wrap"))),
+                (3.0, WrappedMeasurementValue::U64(197), None),
+                (4.0, WrappedMeasurementValue::U64(2), Some(String::from("reset"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn wraparound_at_the_maximum_of_u64() {
+        let wrap = CounterDecrease::Wrap { max_value: u64::MAX };
+        assert_eq!(counter_diff(u64::MAX - 1, 1u64, wrap), (3, Some("wrap")));
+        assert_eq!(counter_diff(u64::MAX, 0u64, wrap), (1, Some("wrap")));
+        assert_eq!(counter_diff(0u64, u64::MAX, wrap), (u64::MAX, None));
+        // The whole range between the values: the diff does not fit in a u64, it is clamped.
+        assert_eq!(counter_diff(1u64, 0u64, wrap), (u64::MAX, Some("wrap")));
+
+        let mut transform = diff_transform::<u64>(wrap);
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(u64::MAX - 1)),
+            counter_point(2, WrappedMeasurementValue::U64(1)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![(2.0, WrappedMeasurementValue::U64(3), Some(String::from("wrap")))]
+        );
+    }
+
+    #[test]
+    fn diff_of_a_float_counter() {
+        let mut transform = diff_transform::<f64>(CounterDecrease::Wrap { max_value: 10 });
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::F64(1.5)),
+            counter_point(2, WrappedMeasurementValue::F64(4.0)),
+            counter_point(3, WrappedMeasurementValue::F64(1.0)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![
+                (2.0, WrappedMeasurementValue::F64(2.5), None),
+                (3.0, WrappedMeasurementValue::F64(7.0), Some(String::from("wrap"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrong_counter_type_is_not_fatal() {
+        let mut transform = diff_transform::<u64>(CounterDecrease::Reset);
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(1)),
+            counter_point(2, WrappedMeasurementValue::F64(2.0)),
+            counter_point(3, WrappedMeasurementValue::U64(3)),
+        ]);
+        let res = transform.process(&mut buf);
+        assert!(matches!(res, Err(TransformError::UnexpectedInput(_))), "{res:?}");
+        // The points of the right type are still processed.
+        assert_eq!(outputs(&buf), vec![(3.0, WrappedMeasurementValue::U64(2), None)]);
+
+        let mut transform = rate_transform::<f64>(CounterDecrease::Reset);
+        let res = transform.process(&mut buf);
+        assert!(matches!(res, Err(TransformError::UnexpectedInput(_))), "{res:?}");
+    }
+
+    #[test]
+    fn rate_per_second() {
+        let mut transform = rate_transform::<u64>(CounterDecrease::Reset);
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(10)),
+            counter_point(3, WrappedMeasurementValue::U64(30)),
+            counter_point(4, WrappedMeasurementValue::U64(5)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(
+            outputs(&buf),
+            vec![
+                (3.0, WrappedMeasurementValue::F64(10.0), None),
+                (4.0, WrappedMeasurementValue::F64(5.0), Some(String::from("reset"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_ignores_out_of_order_and_duplicate_timestamps() {
+        let mut transform = rate_transform::<u64>(CounterDecrease::Reset);
+        let mut buf = buffer(vec![counter_point(2, WrappedMeasurementValue::U64(20))]);
+        transform.process(&mut buf).unwrap();
+        assert!(outputs(&buf).is_empty());
+
+        // Out-of-order: older than the state of the series.
+        // Duplicate: same timestamp as the state, the first point is kept.
+        let mut buf = buffer(vec![
+            counter_point(1, WrappedMeasurementValue::U64(10)),
+            counter_point(2, WrappedMeasurementValue::U64(25)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert!(outputs(&buf).is_empty());
+
+        let mut buf = buffer(vec![counter_point(4, WrappedMeasurementValue::U64(40))]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(outputs(&buf), vec![(4.0, WrappedMeasurementValue::F64(10.0), None)]);
+
+        // Both points in the same buffer: the duplicate is ignored, no infinite rate.
+        let mut buf = buffer(vec![
+            counter_point(5, WrappedMeasurementValue::U64(50)),
+            counter_point(5, WrappedMeasurementValue::U64(60)),
+        ]);
+        transform.process(&mut buf).unwrap();
+        assert_eq!(outputs(&buf), vec![(5.0, WrappedMeasurementValue::F64(10.0), None)]);
+    }
+
+    #[test]
+    fn rate_unit() {
+        assert_eq!(per_second(Unit::Joule), Unit::Watt);
+        let unit = per_second(Unit::Byte);
+        assert_eq!(unit.unique_name(), "By/s");
+        assert_eq!(unit.to_string(), "B/s");
+    }
+
+    fn config(path: PathBuf, format: OutputFormat) -> OutputConfig {
+        OutputConfig {
+            path,
+            append: false,
+            sync: SyncPolicy::OnStop,
+            rotation: RotationConfig::default(),
+            format,
+            socket: None,
+            asynchronous: false,
+            queue_capacity: 16,
+        }
+    }
+
+    /// The definitions of the metrics, in a map instead of the registry of Alumet.
+    fn metrics() -> HashMap<RawMetricId, Metric> {
+        let counter = Metric {
+            name: String::from("example_source_call_counter"),
+            description: String::from("number of times the example source has been called"),
+            value_type: WrappedMeasurementType::U64,
+            unit: PrefixedUnit::from(Unit::Unity),
+        };
+        let rate = Metric {
+            name: String::from("example_source_call_rate"),
+            description: String::from("number of times the example source has been called per second"),
+            value_type: WrappedMeasurementType::F64,
+            unit: PrefixedUnit::from(per_second(Unit::Unity)),
+        };
+        HashMap::from([(counter_metric(), counter), (output_metric(), rate)])
+    }
+
+    impl MetricLookup for HashMap<RawMetricId, Metric> {
+        async fn format_buffer(
+            &self,
+            format: &OutputFormat,
+            measurements: &MeasurementBuffer,
+        ) -> anyhow::Result<Vec<String>> {
+            format_buffer(format, measurements, |id| self.get(id))
+        }
+    }
+
+    /// Some buffers, with an attribute that must be escaped in most formats.
+    fn measurement_buffers() -> Vec<MeasurementBuffer> {
+        let counter =
+            |t: u64| counter_point(t, WrappedMeasurementValue::U64(t * 10)).with_attr("counter", "a, \"b\" = c");
+        let rate = |t: u64| {
+            let mut m = counter_point(t, WrappedMeasurementValue::F64(10.0));
+            m.metric = output_metric();
+            m
+        };
+        vec![
+            buffer(vec![counter(1), counter(2)]),
+            buffer(vec![counter(3), rate(3)]),
+            buffer(vec![counter(4)]),
+        ]
+    }
+
+    /// Writes the buffers with the blocking output.
+    fn write_blocking(config: &OutputConfig, buffers: &[MeasurementBuffer]) {
+        let metrics = metrics();
+        let mut output = ExampleOutput {
+            sink: config.open().unwrap(),
+            format: config.format.clone(),
+            sync_policy: config.sync,
+            last_sync: Instant::now(),
+        };
+        for measurements in buffers {
+            output.write_with(measurements, |id| metrics.get(id)).unwrap();
+        }
+    }
+
+    /// Writes the buffers with the asynchronous output.
+    fn write_async(config: &OutputConfig, buffers: &[MeasurementBuffer]) {
+        let stream = futures::stream::iter(buffers.iter().cloned().map(Ok).collect::<Vec<_>>());
+        let stream = AsyncOutputStream(Box::pin(stream));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(run_async_output(stream, metrics(), config.clone()))
+            .unwrap();
+    }
+
+    /// Writes the buffers with both variants, in two files, and returns both files.
+    ///
+    /// The first `n_initial` buffers are written before the others, in a first run of the output.
+    fn write_files(config: &OutputConfig, buffers: &[MeasurementBuffer], n_initial: usize) -> (String, String) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut written = Vec::new();
+        for (name, write) in [("blocking", write_blocking as fn(&_, &_)), ("async", write_async)] {
+            let config = OutputConfig {
+                path: dir.path().join(name),
+                ..config.clone()
+            };
+            if n_initial > 0 {
+                write(&config, &buffers[..n_initial]);
+            }
+            write(&config, &buffers[n_initial..]);
+            written.push(std::fs::read_to_string(&config.path).unwrap());
+        }
+        let async_file = written.pop().unwrap();
+        (written.pop().unwrap(), async_file)
+    }
+
+    /// Formats the buffers, which is what both outputs should write.
+    fn expected_lines(format: &OutputFormat, buffers: &[MeasurementBuffer]) -> Vec<String> {
+        let metrics = metrics();
+        buffers
+            .iter()
+            .flat_map(|buf| format_buffer(format, buf, |id| metrics.get(id)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn an_old_config_still_loads() {
+        // Before the list of counters, the config only contained the poll interval.
+        #[derive(Serialize)]
+        struct OldConfig {
+            #[serde(with = "humantime_serde")]
+            poll_interval: Duration,
+        }
+        let old = serialize_config(OldConfig {
+            poll_interval: Duration::from_secs(2),
+        })
+        .unwrap();
+        let config: Config = deserialize_config(old).unwrap();
+        assert_eq!(config.counters.len(), 1);
+        assert_eq!(config.output.path, PathBuf::from("alumet-tutorial-output.txt"));
+    }
+
+    #[test]
+    fn reject_an_empty_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path().join("out.txt"), OutputFormat::Text);
+        config.check().unwrap();
+        config.asynchronous = true;
+        config.check().unwrap();
+        config.queue_capacity = 0;
+        let err = config.check().unwrap_err();
+        assert!(err.to_string().contains("queue_capacity"), "{err}");
+    }
+
+    #[test]
+    fn reject_the_rotation_of_the_async_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path().join("out.txt"), OutputFormat::Text);
+        config.rotation.max_size = Some(1024);
+        config.check().unwrap();
+        config.asynchronous = true;
+        let err = config.check().unwrap_err();
+        assert!(err.to_string().contains("rotate"), "{err}");
+
+        config.rotation.max_size = None;
+        config.rotation.max_age = Some(Duration::from_secs(3600));
+        config.check().unwrap_err();
+    }
+
+    #[test]
+    fn both_outputs_write_the_same_file() {
+        let formats = [
+            OutputFormat::Text,
+            OutputFormat::Csv(CsvOptions::default()),
+            OutputFormat::JsonLines(JsonLinesOptions::default()),
+            OutputFormat::LineProtocol,
+        ];
+        for format in formats {
+            let mut config = config(PathBuf::new(), format);
+            // A small queue, which fills up quickly.
+            config.queue_capacity = 1;
+            let buffers = measurement_buffers();
+            let (blocking, async_file) = write_files(&config, &buffers, 0);
+            assert_eq!(blocking, async_file, "format {:?}", config.format);
+            assert_eq!(
+                blocking,
+                config.header() + &expected_lines(&config.format, &buffers).concat()
+            );
+        }
+    }
+
+    #[test]
+    fn both_outputs_write_the_header_before_the_first_line() {
+        // Nothing is written, not even the header, as long as there is no measurement.
+        let config = config(PathBuf::new(), OutputFormat::Csv(CsvOptions::default()));
+        let (blocking, async_file) = write_files(&config, &[], 0);
+        assert_eq!(blocking, "");
+        assert_eq!(async_file, "");
+    }
+
+    #[test]
+    fn both_outputs_append_to_the_same_file() {
+        let mut config = config(PathBuf::new(), OutputFormat::Csv(CsvOptions::default()));
+        config.append = true;
+        let buffers = measurement_buffers();
+        let (blocking, async_file) = write_files(&config, &buffers, 1);
+        assert_eq!(blocking, async_file);
+        // The header is written once, at the beginning of the file.
+        assert_eq!(
+            blocking,
+            config.header() + &expected_lines(&config.format, &buffers).concat()
+        );
+    }
+
+    #[test]
+    fn both_outputs_send_one_datagram_per_line() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut config = config(PathBuf::new(), OutputFormat::LineProtocol);
+        config.socket = Some(SocketConfig {
+            protocol: SocketProtocol::Udp,
+            address: server.local_addr().unwrap().to_string(),
+        });
+        let buffers = measurement_buffers();
+        let lines = expected_lines(&config.format, &buffers);
+        assert_eq!(lines.len(), 5);
+
+        let receive = || -> Vec<String> {
+            let mut buf = [0; 1024];
+            (0..lines.len())
+                .map(|_| {
+                    let n = server.recv(&mut buf).unwrap();
+                    String::from_utf8(buf[..n].to_vec()).unwrap()
+                })
+                .collect()
+        };
+        write_blocking(&config, &buffers);
+        assert_eq!(receive(), lines);
+        write_async(&config, &buffers);
+        assert_eq!(receive(), lines);
+    }
+}
@@ -0,0 +1,531 @@
+//! Additional sources and outputs of the example plugin, which are not described in the tutorial.
+//!
+//! They are all disabled by default. Their options are written next to the options of the tutorial,
+//! for instance in the `[plugins.example.sqlite]` table.
+
+use std::collections::{HashMap, HashSet};
+
+use alumet::measurement::WrappedMeasurementType;
+use alumet::metrics::{MetricId, TypedMetricId};
+use alumet::pipeline::trigger;
+use alumet::plugin::AlumetPluginStart;
+use alumet::units::Unit;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::output::binary::{BinaryConfig, BinaryOutput};
+use crate::output::parquet::{ParquetConfig, ParquetOutput};
+use crate::output::sqlite::{SqliteConfig, SqliteOutput};
+use crate::source::battery::{self, BatteryConfig, BatterySource};
+use crate::source::cgroup::{self, CgroupConfig, CgroupSource};
+use crate::source::diskstats::{self, DiskstatsConfig, DiskstatsSource};
+use crate::source::hwmon::{self, HwmonConfig, HwmonSource};
+use crate::source::net_dev::{self, NetDevConfig, NetDevSource};
+use crate::source::proc_stat::{self, ProcStatConfig, ProcStatSource};
+use crate::source::process::{self, ProcessConfig, ProcessSource};
+use crate::source::rapl::{self, RaplConfig, RaplSource};
+use crate::source::replay::{self, RecordedMetric, ReplayConfig, ReplaySource};
+use crate::source::signal::{Signal, SignalConfig, SignalSource};
+
+use super::{per_second, CounterDecrease, RateTransform};
+
+/// The metric of the counter sources, which the replay reuses for the recorded counter.
+const COUNTER_METRIC: &str = "example_source_call_counter";
+
+/// The other metrics that are created by the tutorial part of the plugin.
+const TUTORIAL_METRICS: [&str; 2] = ["example_source_call_diff", "example_source_call_rate"];
+
+/// Metrics of the rate transforms that are added with the sources.
+const RAPL_POWER_METRIC: &str = "example_rapl_power";
+const NET_THROUGHPUT_METRIC: &str = "example_net_throughput";
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtrasConfig {
+    /// SQLite database.
+    sqlite: SqliteConfig,
+
+    /// Parquet file, for offline analysis.
+    parquet: ParquetConfig,
+
+    /// Compact binary file.
+    binary: BinaryConfig,
+
+    /// Replay of a recorded output file.
+    replay: ReplayConfig,
+
+    /// Synthetic signals.
+    signals: SignalConfig,
+
+    /// CPU time from /proc/stat.
+    proc_stat: ProcStatConfig,
+
+    /// Resource usage of the processes, from /proc/[pid].
+    processes: ProcessConfig,
+
+    /// Resource usage of the cgroups (v2).
+    cgroups: CgroupConfig,
+
+    /// Energy consumption measured by RAPL.
+    rapl: RaplConfig,
+
+    /// Hardware sensors from hwmon and the thermal zones.
+    hwmon: HwmonConfig,
+
+    /// Network interfaces statistics from /proc/net/dev.
+    net_dev: NetDevConfig,
+
+    /// IO of the block devices from /proc/diskstats.
+    diskstats: DiskstatsConfig,
+
+    /// Batteries from /sys/class/power_supply.
+    battery: BatteryConfig,
+}
+
+impl ExtrasConfig {
+    /// Checks the config of the additional sources and outputs.
+    ///
+    /// The recording is read here, because the names of its metrics must not clash with the metrics of the other sources.
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.signals.check()?;
+        self.processes.check()?;
+        self.net_dev.check()?;
+        self.diskstats.check()?;
+        if self.replay.enabled {
+            let points = replay::read_recording(&self.replay.path, self.replay.format)?;
+            check_replayed_metrics(&replay::recorded_metrics(&points)?, &self.replay, &self.metric_names())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the names of the metrics created by the plugin, except the ones of the replay.
+    fn metric_names(&self) -> Vec<&str> {
+        let mut names = vec![COUNTER_METRIC];
+        names.extend(TUTORIAL_METRICS);
+        if self.signals.enabled {
+            names.extend(self.signals.signals.iter().map(|s| s.name.as_str()));
+        }
+        let sources: [(bool, &[&str]); 10] = [
+            (self.proc_stat.enabled, &proc_stat::METRICS),
+            (self.processes.enabled, &process::METRICS),
+            (self.cgroups.enabled, &cgroup::METRICS),
+            (self.rapl.enabled, &rapl::METRICS),
+            (self.rapl.enabled, &[RAPL_POWER_METRIC]),
+            (self.hwmon.enabled, &hwmon::METRICS),
+            (self.net_dev.enabled, &net_dev::METRICS),
+            (self.net_dev.enabled, &[NET_THROUGHPUT_METRIC]),
+            (self.diskstats.enabled, &diskstats::METRICS),
+            (self.battery.enabled, &battery::METRICS),
+        ];
+        for (enabled, metrics) in sources {
+            if enabled {
+                names.extend(metrics);
+            }
+        }
+        names
+    }
+
+    /// Adds the enabled sources, transforms and outputs to the pipeline.
+    ///
+    /// `counter_metric` is the metric of the counter sources of the tutorial.
+    pub fn start(&self, alumet: &mut AlumetPluginStart, counter_metric: TypedMetricId<u64>) -> anyhow::Result<()> {
+        // Replay a recording alongside the counter sources
+        if self.replay.enabled {
+            add_replay_source(alumet, &self.replay, counter_metric)?;
+        }
+
+        // Generate synthetic signals
+        if self.signals.enabled {
+            add_signal_source(alumet, &self.signals)?;
+        }
+
+        // Measure the CPU time from /proc/stat
+        if self.proc_stat.enabled {
+            let source = ProcStatSource::new(&self.proc_stat, alumet)?;
+            let trigger = trigger::builder::time_interval(self.proc_stat.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Measure the processes that match the filter
+        if self.processes.enabled {
+            let source = ProcessSource::new(&self.processes, alumet)?;
+            let trigger = trigger::builder::time_interval(self.processes.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Measure the cgroups
+        if self.cgroups.enabled {
+            let source = CgroupSource::new(&self.cgroups, alumet)?;
+            let trigger = trigger::builder::time_interval(self.cgroups.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Measure the energy consumption with RAPL, and compute the power with a rate transform
+        if self.rapl.enabled {
+            let source = RaplSource::new(&self.rapl, alumet)?;
+            let energy_metric = source.energy_metric();
+            let trigger = trigger::builder::time_interval(self.rapl.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+
+            let power_metric = alumet.create_metric::<f64>(
+                RAPL_POWER_METRIC,
+                per_second(Unit::Joule),
+                "average power consumed by the RAPL domain between two measurements",
+            )?;
+            let power_transform = RateTransform::<f64> {
+                counter_metric: energy_metric.untyped_id(),
+                previous_counters: HashMap::new(),
+                rate_metric: power_metric.untyped_id(),
+                // The energy counters never wrap around, the source takes care of it.
+                on_decrease: CounterDecrease::Reset,
+            };
+            alumet.add_transform(Box::new(power_transform));
+        }
+
+        // Measure the hardware sensors (temperature, power, voltage, fans)
+        if self.hwmon.enabled {
+            let source = HwmonSource::new(&self.hwmon, alumet)?;
+            let trigger = trigger::builder::time_interval(self.hwmon.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Measure the network interfaces, and compute their throughput with a rate transform
+        if self.net_dev.enabled {
+            let source = NetDevSource::new(&self.net_dev, alumet)?;
+            let bytes_metric = source.bytes_metric();
+            let trigger = trigger::builder::time_interval(self.net_dev.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+
+            let throughput_metric = alumet.create_metric::<f64>(
+                NET_THROUGHPUT_METRIC,
+                per_second(Unit::Byte),
+                "average throughput of the network interface between two measurements",
+            )?;
+            let throughput_transform = RateTransform::<u64> {
+                counter_metric: bytes_metric.untyped_id(),
+                previous_counters: HashMap::new(),
+                rate_metric: throughput_metric.untyped_id(),
+                // The counters are reset when the interface is removed and added again.
+                on_decrease: CounterDecrease::Reset,
+            };
+            alumet.add_transform(Box::new(throughput_transform));
+        }
+
+        // Measure the IO of the block devices
+        if self.diskstats.enabled {
+            let source = DiskstatsSource::new(&self.diskstats, alumet)?;
+            let trigger = trigger::builder::time_interval(self.diskstats.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Measure the batteries
+        if self.battery.enabled {
+            let source = BatterySource::new(&self.battery, alumet)?;
+            let trigger = trigger::builder::time_interval(self.battery.poll_interval).build()?;
+            alumet.add_source(Box::new(source), trigger);
+        }
+
+        // Store the measurements in a SQLite database
+        if self.sqlite.enabled {
+            let sqlite = SqliteOutput::open(&self.sqlite.path)?;
+            alumet.add_blocking_output(Box::new(sqlite));
+        }
+
+        // Write the measurements to a Parquet file
+        if self.parquet.enabled {
+            let parquet = ParquetOutput::create(&self.parquet)?;
+            alumet.add_blocking_output(Box::new(parquet));
+        }
+
+        // Write the measurements to a compact binary file
+        if self.binary.enabled {
+            let binary = BinaryOutput::create(&self.binary)?;
+            alumet.add_blocking_output(Box::new(binary));
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the replayed metrics do not have the same name as the metrics of the plugin, listed in `plugin_metrics`.
+///
+/// The recorded counter is the only exception: it is replayed with the metric of the counter sources.
+/// The metrics that are skipped by the replay are not checked.
+fn check_replayed_metrics(
+    recorded: &[RecordedMetric],
+    config: &ReplayConfig,
+    plugin_metrics: &[&str],
+) -> anyhow::Result<()> {
+    let plugin_metrics: HashSet<&str> = plugin_metrics.iter().copied().collect();
+    for m in recorded {
+        if m.name != COUNTER_METRIC
+            && !config.skip_metrics.contains(&m.name)
+            && plugin_metrics.contains(m.name.as_str())
+        {
+            return Err(anyhow!(
+                "the recording {} contains the metric {}, which is also created by the plugin: add it to the skip_metrics of the replay, or disable the source that creates it",
+                config.path.display(),
+                m.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Adds a source that replays the points of a recording.
+///
+/// The recorded counter reuses the metric of the counter source, so that the transforms process it
+/// like the measurements of the source. The other recorded metrics are created here, with their recorded unit.
+/// The replayed points have the attribute `replayed`, which separates them from the series of the source.
+fn add_replay_source(
+    alumet: &mut AlumetPluginStart,
+    config: &ReplayConfig,
+    counter_metric: TypedMetricId<u64>,
+) -> anyhow::Result<()> {
+    let mut points = replay::read_recording(&config.path, config.format)?;
+    points.retain(|p| !config.skip_metrics.contains(&p.metric));
+
+    let mut metrics = HashMap::new();
+    for m in replay::recorded_metrics(&points)? {
+        let (id, value_type) = if m.name == COUNTER_METRIC {
+            // The counter metric has been registered as a u64 metric, whatever the recording contains.
+            (counter_metric.untyped_id(), WrappedMeasurementType::U64)
+        } else {
+            let id = alumet.create_metric_untyped(
+                &m.name,
+                m.value_type.clone(),
+                m.unit,
+                "metric replayed from a recording",
+            )?;
+            (id, m.value_type)
+        };
+        metrics.insert(m.name, (id, value_type));
+    }
+
+    log::info!("Replaying {} points from {}", points.len(), config.path.display());
+    let source = ReplaySource::new(points, metrics, config.pace)?;
+    let trigger = trigger::builder::time_interval(config.poll_interval).build()?;
+    alumet.add_source(Box::new(source), trigger);
+    Ok(())
+}
+
+/// Adds a source that generates the synthetic signals, with one metric per signal.
+fn add_signal_source(alumet: &mut AlumetPluginStart, config: &SignalConfig) -> anyhow::Result<()> {
+    let mut signals = Vec::with_capacity(config.signals.len());
+    for definition in &config.signals {
+        let metric = alumet.create_metric_untyped(
+            &definition.name,
+            definition.value_type.wrapped(),
+            Unit::Unity,
+            "synthetic signal",
+        )?;
+        signals.push(Signal::new(definition.clone(), metric)?);
+    }
+    let source = SignalSource::new(signals, config.poll_interval);
+    let trigger = trigger::builder::time_interval(config.poll_interval).build()?;
+    alumet.add_source(Box::new(source), trigger);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::{MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use alumet::metrics::{Metric, RawMetricId};
+    use alumet::pipeline::Source;
+    use alumet::plugin::rust::{deserialize_config, serialize_config};
+    use alumet::resources::{Resource, ResourceConsumer};
+
+    use crate::output::csv::CsvOptions;
+    use crate::output::jsonl::JsonLinesOptions;
+    use crate::source::replay::{ReplayFormat, ReplayPace};
+
+    use super::super::{format_record, Config, OutputFormat};
+    use super::*;
+
+    /// Writes a recording in the JSON Lines format, with one point per metric.
+    fn recording(metrics: &[&str]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for metric in metrics {
+            writeln!(
+                file,
+                r#"{{"timestamp":1,"metric":"{metric}","unit":"1","value_type":"u64","value":1,"resource_kind":"local_machine","resource_id":"","consumer_kind":"local_machine","consumer_id":"","attributes":{{}}}}"#
+            )
+            .unwrap();
+        }
+        file
+    }
+
+    fn replay_config(recording: &tempfile::NamedTempFile) -> ExtrasConfig {
+        let mut config = ExtrasConfig::default();
+        config.replay.enabled = true;
+        config.replay.path = recording.path().to_owned();
+        config.replay.format = ReplayFormat::JsonLines;
+        config
+    }
+
+    #[test]
+    fn reject_a_replayed_metric_of_an_enabled_source() {
+        let recording = recording(&["example_source_call_counter", "example_cpu_time"]);
+        let mut config = replay_config(&recording);
+        config.check().unwrap();
+
+        config.proc_stat.enabled = true;
+        let err = config.check().unwrap_err();
+        assert!(err.to_string().contains("metric example_cpu_time"), "{err}");
+
+        // The metric can be skipped.
+        config.replay.skip_metrics.push(String::from("example_cpu_time"));
+        config.check().unwrap();
+    }
+
+    #[test]
+    fn reject_a_replayed_metric_of_the_tutorial() {
+        let recording = recording(&["example_source_call_rate"]);
+        let mut config = replay_config(&recording);
+        // Skipped by default.
+        config.check().unwrap();
+
+        config.replay.skip_metrics.clear();
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn reject_a_replayed_metric_of_a_rate_transform() {
+        let recording = recording(&["example_rapl_power"]);
+        let mut config = replay_config(&recording);
+        config.check().unwrap();
+
+        config.rapl.enabled = true;
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn reject_a_replayed_metric_of_a_signal() {
+        let recording = recording(&["example_signal_sine"]);
+        let mut config = replay_config(&recording);
+        config.check().unwrap();
+
+        config.signals.enabled = true;
+        assert!(config.check().is_err());
+    }
+
+    /// Points with an unusual resource, consumer and attribute, which the recording must preserve.
+    fn recorded_points() -> Vec<MeasurementPoint> {
+        let point = |id: u64, secs: u64, value: WrappedMeasurementValue| {
+            MeasurementPoint::new_untyped(
+                Timestamp::from(UNIX_EPOCH + Duration::new(secs, 123_456_789)),
+                RawMetricId::from_u64(id),
+                Resource::CpuPackage { id: 1 },
+                ResourceConsumer::Process { pid: 42 },
+                value,
+            )
+            .with_attr("domain", "a, \"b\" = c")
+        };
+        vec![
+            point(0, 1_700_000_000, WrappedMeasurementValue::U64(u64::MAX - 1)),
+            point(1, 1_700_000_001, WrappedMeasurementValue::F64(2.5)),
+            point(1, 1_700_000_002, WrappedMeasurementValue::F64(3.0)),
+        ]
+    }
+
+    fn metric(name: &str, value_type: WrappedMeasurementType) -> Metric {
+        Metric {
+            name: name.to_owned(),
+            description: String::new(),
+            value_type,
+            unit: Unit::Unity.into(),
+        }
+    }
+
+    /// Writes the points with the given format, then replays them.
+    fn write_and_replay(format: OutputFormat, replay_format: ReplayFormat) -> Vec<MeasurementPoint> {
+        let metrics = HashMap::from([
+            (
+                RawMetricId::from_u64(0),
+                metric("energy_counter", WrappedMeasurementType::U64),
+            ),
+            (RawMetricId::from_u64(1), metric("power", WrappedMeasurementType::F64)),
+        ]);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        if let OutputFormat::Csv(csv) = &format {
+            file.write_all(csv.header().as_bytes()).unwrap();
+        }
+        for m in recorded_points() {
+            let line = format_record(&format, &m, &metrics[&m.metric]).unwrap().unwrap();
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        let points = replay::read_recording(file.path(), replay_format).unwrap();
+        let replayed_metrics = replay::recorded_metrics(&points)
+            .unwrap()
+            .into_iter()
+            .map(|m| {
+                let (id, _) = metrics.iter().find(|(_, metric)| metric.name == m.name).unwrap();
+                (m.name, (*id, m.value_type))
+            })
+            .collect();
+        let pace = ReplayPace::AsFastAsPossible {
+            max_points_per_poll: 10,
+        };
+        let mut source = ReplaySource::new(points, replayed_metrics, pace).unwrap();
+        let mut buffer = MeasurementBuffer::new();
+        source
+            .poll(&mut buffer.as_accumulator(), Timestamp::from(UNIX_EPOCH))
+            .unwrap();
+        buffer.into_iter().collect()
+    }
+
+    fn assert_same_points(replayed: &[MeasurementPoint]) {
+        let original = recorded_points();
+        assert_eq!(replayed.len(), original.len());
+        for (r, o) in replayed.iter().zip(&original) {
+            assert_eq!(r.timestamp, o.timestamp);
+            assert_eq!(r.metric, o.metric);
+            assert_eq!(r.value, o.value);
+            assert_eq!(r.resource, o.resource);
+            assert_eq!(r.consumer, o.consumer);
+            let attributes: Vec<(&str, String)> = r.attributes().map(|(k, v)| (k, v.to_string())).collect();
+            assert_eq!(
+                attributes,
+                [
+                    ("domain", String::from("a, \"b\" = c")),
+                    ("replayed", String::from("true"))
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn replay_a_text_recording() {
+        assert_same_points(&write_and_replay(OutputFormat::Text, ReplayFormat::Text));
+    }
+
+    #[test]
+    fn replay_a_csv_recording() {
+        let format = OutputFormat::Csv(CsvOptions::default());
+        assert_same_points(&write_and_replay(format, ReplayFormat::Csv));
+    }
+
+    #[test]
+    fn replay_a_json_lines_recording() {
+        let format = OutputFormat::JsonLines(JsonLinesOptions::default());
+        assert_same_points(&write_and_replay(format, ReplayFormat::JsonLines));
+    }
+
+    #[test]
+    fn options_are_next_to_the_tutorial_options() {
+        let mut config = Config::default();
+        config.extras.sqlite.enabled = true;
+        let table = serialize_config(config).unwrap();
+        assert!(table.0.contains_key("output"));
+        assert!(table.0.contains_key("sqlite"));
+        assert!(!table.0.contains_key("extras"));
+
+        let config: Config = deserialize_config(table).unwrap();
+        assert!(config.extras.sqlite.enabled);
+    }
+}
@@ -2,3 +2,5 @@ mod basic;
 mod basic_with_elements;
 mod basic_with_elements_empty;
 mod basic_with_elements_source_without_config;
+mod output;
+mod source;
@@ -0,0 +1,88 @@
+//! Building blocks for the outputs of the example plugin.
+
+use std::borrow::Cow;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use alumet::measurement::Timestamp;
+use serde::{Deserialize, Serialize};
+
+pub mod async_sink;
+pub mod binary;
+pub mod csv;
+pub mod jsonl;
+pub mod line_protocol;
+pub mod parquet;
+pub mod prometheus;
+pub mod rotation;
+pub mod socket;
+pub mod sqlite;
+
+use rotation::RotatingFile;
+use socket::SocketWriter;
+
+/// Where an output writes its records.
+pub enum Sink {
+    File(RotatingFile),
+    Socket(SocketWriter),
+}
+
+impl Sink {
+    /// Writes a complete record.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.write_record(record),
+            Sink::Socket(socket) => socket.write_record(record),
+        }
+    }
+
+    /// Flushes the buffered data and, for files, synchronizes them to the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.sync(),
+            Sink::Socket(socket) => socket.flush(),
+        }
+    }
+}
+
+/// Keys that describe the resource and the consumer of a point, in the formats that write them next to the attributes.
+pub const BUILTIN_KEYS: [&str; 4] = ["resource_kind", "resource_id", "consumer_kind", "consumer_id"];
+
+/// Returns the key under which an attribute is written next to the [`BUILTIN_KEYS`].
+///
+/// An attribute cannot replace a built-in key: its key is prefixed with `attr_` instead.
+pub fn attribute_key(key: &str) -> Cow<'_, str> {
+    if BUILTIN_KEYS.contains(&key) {
+        Cow::Owned(format!("attr_{key}"))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+/// How to write a timestamp.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    /// Date and time in UTC, as specified by RFC 3339, with a nanosecond precision.
+    Rfc3339,
+    /// Number of nanoseconds since the UNIX epoch.
+    EpochNanos,
+}
+
+/// Formats a timestamp according to `format`.
+pub fn format_timestamp(timestamp: Timestamp, format: TimestampFormat) -> String {
+    match format {
+        TimestampFormat::Rfc3339 => humantime::format_rfc3339_nanos(SystemTime::from(timestamp)).to_string(),
+        TimestampFormat::EpochNanos => unix_nanos(timestamp).to_string(),
+    }
+}
+
+/// Returns the number of nanoseconds between the UNIX epoch and `timestamp`.
+///
+/// Timestamps before the epoch are negative.
+pub fn unix_nanos(timestamp: Timestamp) -> i128 {
+    match SystemTime::from(timestamp).duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
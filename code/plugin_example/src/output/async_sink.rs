@@ -0,0 +1,100 @@
+//! Non-blocking variant of [`Sink`](super::Sink), based on tokio.
+
+use std::io;
+use std::path::Path;
+
+use anyhow::Context;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::{TcpStream, UdpSocket};
+
+use super::socket::{SocketConfig, SocketProtocol};
+
+/// Where an asynchronous output writes its records.
+///
+/// Unlike [`Sink`](super::Sink), files are not rotated.
+pub enum AsyncSink {
+    File {
+        writer: BufWriter<File>,
+        /// Header that has not been written yet: it is written before the first record of an empty file.
+        pending_header: Vec<u8>,
+    },
+    Tcp(BufWriter<TcpStream>),
+    /// Each record is sent in its own datagram.
+    Udp(UdpSocket),
+}
+
+impl AsyncSink {
+    /// Opens the file at `path`, in append or truncate mode.
+    ///
+    /// If the file is empty, `header` is written before its first record, like [`RotatingFile`](super::rotation::RotatingFile) does.
+    pub async fn open_file(path: &Path, append: bool, header: &[u8]) -> io::Result<Self> {
+        let mut options = OpenOptions::new();
+        if append {
+            options.append(true);
+        } else {
+            options.write(true).truncate(true);
+        }
+        let file = options.create(true).open(path).await?;
+        let is_empty = file.metadata().await?.len() == 0;
+        Ok(AsyncSink::File {
+            writer: BufWriter::new(file),
+            pending_header: if is_empty { header.to_vec() } else { Vec::new() },
+        })
+    }
+
+    /// Connects to the socket, then sends `header` (if it is not empty).
+    pub async fn connect(config: &SocketConfig, header: &[u8]) -> anyhow::Result<Self> {
+        let addr = config.resolve()?;
+        let mut sink = match config.protocol {
+            SocketProtocol::Tcp => {
+                let stream = TcpStream::connect(addr)
+                    .await
+                    .with_context(|| format!("failed to connect to {addr}"))?;
+                AsyncSink::Tcp(BufWriter::new(stream))
+            }
+            SocketProtocol::Udp => {
+                let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(local).await.context("failed to bind the UDP socket")?;
+                socket
+                    .connect(addr)
+                    .await
+                    .with_context(|| format!("failed to connect the UDP socket to {addr}"))?;
+                AsyncSink::Udp(socket)
+            }
+        };
+        if !header.is_empty() {
+            sink.write_record(header)
+                .await
+                .with_context(|| format!("failed to send the header to {addr}"))?;
+        }
+        Ok(sink)
+    }
+
+    /// Writes a complete record.
+    pub async fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        match self {
+            AsyncSink::File { writer, pending_header } => {
+                if !pending_header.is_empty() {
+                    writer.write_all(pending_header).await?;
+                    pending_header.clear();
+                }
+                writer.write_all(record).await
+            }
+            AsyncSink::Tcp(stream) => stream.write_all(record).await,
+            AsyncSink::Udp(socket) => socket.send(record).await.map(|_| ()),
+        }
+    }
+
+    /// Flushes the buffered data and, for files, synchronizes them to the disk.
+    pub async fn sync(&mut self) -> io::Result<()> {
+        match self {
+            AsyncSink::File { writer, .. } => {
+                writer.flush().await?;
+                writer.get_ref().sync_data().await
+            }
+            AsyncSink::Tcp(stream) => stream.flush().await,
+            AsyncSink::Udp(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,725 @@
+//! Compact binary format for the measurements, and a reader that decodes it.
+//!
+//! A binary file starts with a header: the magic bytes [`MAGIC`] and a byte of flags.
+//! It is followed by a sequence of frames, one per call to `write`. Each frame is prefixed by its length (a varint)
+//! and, if the flag [`FLAG_ZSTD`] is set, compressed with zstd.
+//!
+//! A frame contains a sequence of records, which start with a tag:
+//! - [`TAG_METRIC`]: definition of a metric, written before its first point: id, name, unit, value type.
+//! - [`TAG_STRING`]: definition of an interned string, which is then referred to by its index.
+//!   The indices start at 0 and are incremented by one for each new string.
+//! - [`TAG_POINT`]: a measurement point.
+//!   Its timestamp is encoded as the difference with the timestamp of the previous point (which can be negative),
+//!   its resource, consumer and attribute keys are interned strings.
+//!
+//! The integers are encoded as [LEB128](https://en.wikipedia.org/wiki/LEB128) varints,
+//! the signed integers are zigzag-encoded first, and the floats are written as 8 little-endian bytes.
+//! The dictionaries (metrics and strings) are valid for the whole file, not only for the frame that defines them.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use alumet::measurement::{
+    AttributeValue, MeasurementBuffer, MeasurementPoint, Timestamp, WrappedMeasurementType, WrappedMeasurementValue,
+};
+use alumet::metrics::RawMetricId;
+use alumet::pipeline::elements::error::WriteError;
+use alumet::pipeline::elements::output::OutputContext;
+use alumet::pipeline::Output;
+use alumet::resources::{Resource, ResourceConsumer};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use super::unix_nanos;
+
+/// Magic bytes at the beginning of the file, with the version of the format.
+pub const MAGIC: &[u8; 8] = b"ALUMETB1";
+/// Flag set when the frames are compressed with zstd.
+pub const FLAG_ZSTD: u8 = 0b1;
+
+/// Tag of a metric definition.
+pub const TAG_METRIC: u8 = 0;
+/// Tag of a string definition.
+pub const TAG_STRING: u8 = 1;
+/// Tag of a measurement point.
+pub const TAG_POINT: u8 = 2;
+
+const VALUE_U64: u8 = 0;
+const VALUE_F64: u8 = 1;
+
+const ATTR_U64: u8 = 0;
+const ATTR_F64: u8 = 1;
+const ATTR_BOOL: u8 = 2;
+const ATTR_STRING: u8 = 3;
+
+/// Configuration of the binary output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryConfig {
+    /// If true, the measurements are written to the binary file.
+    pub enabled: bool,
+    /// Path of the binary file. It is overwritten if it exists.
+    pub path: PathBuf,
+    /// Level of zstd compression of the frames, or nothing to disable the compression.
+    pub zstd_level: Option<i32>,
+}
+
+impl Default for BinaryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("alumet-tutorial-output.bin"),
+            zstd_level: Some(3),
+        }
+    }
+}
+
+/// Writes the measurements to a file, in the binary format.
+pub struct BinaryOutput<W: Write = BufWriter<File>> {
+    writer: W,
+    zstd_level: Option<i32>,
+    encoder: Encoder,
+}
+
+/// The state of the encoder, which persists between the frames.
+#[derive(Default)]
+struct Encoder {
+    /// Metrics that have already been defined, with their value type and the order of their definition.
+    metrics: HashMap<RawMetricId, (WrappedMeasurementType, usize)>,
+    /// Strings that have already been defined, with their index.
+    strings: HashMap<String, u64>,
+    /// Timestamp of the previous point, in nanoseconds since the UNIX epoch.
+    last_timestamp: i64,
+}
+
+impl BinaryOutput {
+    /// Creates the binary file at `path` and writes its header.
+    pub fn create(config: &BinaryConfig) -> anyhow::Result<Self> {
+        let path = &config.path;
+        let file = File::create(path).with_context(|| format!("failed to create binary file {}", path.display()))?;
+        Self::new(BufWriter::new(file), config.zstd_level)
+            .with_context(|| format!("failed to write the header of {}", path.display()))
+    }
+}
+
+impl<W: Write> BinaryOutput<W> {
+    /// Writes the header of the format to `writer`, and returns an output that writes the frames to it.
+    pub fn new(mut writer: W, zstd_level: Option<i32>) -> io::Result<Self> {
+        let flags = if zstd_level.is_some() { FLAG_ZSTD } else { 0 };
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[flags])?;
+        Ok(Self {
+            writer,
+            zstd_level,
+            encoder: Encoder::default(),
+        })
+    }
+
+    /// Encodes the measurements as a frame and writes it.
+    ///
+    /// Use this method to write measurements without the pipeline of Alumet,
+    /// for instance in tests. `metric_type` must return the name, unit and value type of each metric.
+    pub fn write_frame(
+        &mut self,
+        measurements: &MeasurementBuffer,
+        metric_type: impl Fn(&RawMetricId) -> anyhow::Result<MetricInfo>,
+    ) -> anyhow::Result<()> {
+        // The frame defines metrics and strings, which are used by the next frames. If the frame is not written,
+        // they must not be considered as defined: remember the state of the encoder, to restore it on failure.
+        let checkpoint = self.encoder.checkpoint();
+        let res = self.encode_and_write(measurements, metric_type);
+        if res.is_err() {
+            self.encoder.rollback(checkpoint);
+        }
+        res
+    }
+
+    fn encode_and_write(
+        &mut self,
+        measurements: &MeasurementBuffer,
+        metric_type: impl Fn(&RawMetricId) -> anyhow::Result<MetricInfo>,
+    ) -> anyhow::Result<()> {
+        let mut frame = Vec::new();
+        for m in measurements.iter() {
+            self.encoder.encode_point(&mut frame, m, &metric_type)?;
+        }
+        if let Some(level) = self.zstd_level {
+            frame = zstd::encode_all(frame.as_slice(), level)?;
+        }
+        let mut len = Vec::with_capacity(10);
+        write_varint(&mut len, frame.len() as u64);
+        self.writer.write_all(&len)?;
+        self.writer.write_all(&frame)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> Output for BinaryOutput<W> {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        self.write_frame(measurements, |id| {
+            let metric = ctx
+                .metrics
+                .by_id(id)
+                .with_context(|| format!("unregistered metric id: {}", id.as_u64()))?;
+            Ok(MetricInfo {
+                name: metric.name.clone(),
+                unit: metric.unit.unique_name(),
+                value_type: metric.value_type.clone(),
+            })
+        })?;
+        Ok(())
+    }
+}
+
+/// State of the encoder before a frame. The dictionaries only grow, their sizes are enough to restore them.
+struct Checkpoint {
+    n_metrics: usize,
+    n_strings: usize,
+    last_timestamp: i64,
+}
+
+impl Encoder {
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            n_metrics: self.metrics.len(),
+            n_strings: self.strings.len(),
+            last_timestamp: self.last_timestamp,
+        }
+    }
+
+    /// Forgets the definitions made after the checkpoint.
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.metrics.retain(|_, (_, order)| *order < checkpoint.n_metrics);
+        self.strings.retain(|_, index| *index < checkpoint.n_strings as u64);
+        self.last_timestamp = checkpoint.last_timestamp;
+    }
+
+    fn encode_point(
+        &mut self,
+        buf: &mut Vec<u8>,
+        m: &MeasurementPoint,
+        metric_type: impl Fn(&RawMetricId) -> anyhow::Result<MetricInfo>,
+    ) -> anyhow::Result<()> {
+        // Define the metric and the strings before the point that uses them.
+        let n_metrics = self.metrics.len();
+        if let Entry::Vacant(entry) = self.metrics.entry(m.metric) {
+            let info = metric_type(&m.metric)?;
+            buf.push(TAG_METRIC);
+            write_varint(buf, m.metric.as_u64());
+            write_str(buf, &info.name);
+            write_str(buf, &info.unit);
+            buf.push(match info.value_type {
+                WrappedMeasurementType::U64 => VALUE_U64,
+                WrappedMeasurementType::F64 => VALUE_F64,
+            });
+            entry.insert((info.value_type, n_metrics));
+        }
+        let resource_kind = self.intern(buf, m.resource.kind());
+        let resource_id = self.intern(buf, &m.resource.id_display().to_string());
+        let consumer_kind = self.intern(buf, m.consumer.kind());
+        let consumer_id = self.intern(buf, &m.consumer.id_display().to_string());
+        let attributes: Vec<(u64, &AttributeValue)> = m
+            .attributes()
+            .map(|(key, value)| (self.intern(buf, key), value))
+            .collect();
+
+        buf.push(TAG_POINT);
+        write_varint(buf, m.metric.as_u64());
+        let timestamp = i64::try_from(unix_nanos(m.timestamp)).context("timestamp out of range")?;
+        write_signed_varint(buf, timestamp.wrapping_sub(self.last_timestamp));
+        self.last_timestamp = timestamp;
+        // The type of the value is given by the metric, it is checked to be able to decode the point.
+        match (&m.value, &self.metrics[&m.metric].0) {
+            (WrappedMeasurementValue::U64(v), WrappedMeasurementType::U64) => write_varint(buf, *v),
+            (WrappedMeasurementValue::F64(v), WrappedMeasurementType::F64) => buf.extend_from_slice(&v.to_le_bytes()),
+            (value, expected) => {
+                return Err(anyhow!(
+                    "invalid value {value:?} for metric {}: expected {expected:?}",
+                    m.metric.as_u64()
+                ))
+            }
+        }
+        for index in [resource_kind, resource_id, consumer_kind, consumer_id] {
+            write_varint(buf, index);
+        }
+        write_varint(buf, attributes.len() as u64);
+        for (key, value) in attributes {
+            write_varint(buf, key);
+            match value {
+                AttributeValue::U64(v) => {
+                    buf.push(ATTR_U64);
+                    write_varint(buf, *v);
+                }
+                AttributeValue::F64(v) => {
+                    buf.push(ATTR_F64);
+                    buf.extend_from_slice(&v.to_le_bytes());
+                }
+                AttributeValue::Bool(v) => {
+                    buf.push(ATTR_BOOL);
+                    buf.push(*v as u8);
+                }
+                other => {
+                    buf.push(ATTR_STRING);
+                    write_str(buf, &other.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the index of the string `s`, and defines it if it is new.
+    fn intern(&mut self, buf: &mut Vec<u8>, s: &str) -> u64 {
+        if let Some(index) = self.strings.get(s) {
+            return *index;
+        }
+        let index = self.strings.len() as u64;
+        buf.push(TAG_STRING);
+        write_str(buf, s);
+        self.strings.insert(s.to_owned(), index);
+        index
+    }
+}
+
+/// Definition of a metric, as stored in the binary file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricInfo {
+    pub name: String,
+    /// Unique name of the unit.
+    pub unit: String,
+    pub value_type: WrappedMeasurementType,
+}
+
+/// Reads a binary file and decodes its measurement points.
+///
+/// The points are returned with the same metric ids as the ones that were written.
+/// Use [`BinaryReader::metrics`] to get the definition of the metrics.
+///
+/// ## Example
+/// ```ignore
+/// let mut reader = BinaryReader::open("alumet-tutorial-output.bin")?;
+/// for point in &mut reader {
+///     let point = point?;
+///     let metric = &reader.metrics()[&point.metric];
+///     println!("{}: {:?}", metric.name, point.value);
+/// }
+/// ```
+pub struct BinaryReader<R: Read = BufReader<File>> {
+    reader: R,
+    compressed: bool,
+    /// Decoded frame, and position of the next record in it.
+    frame: Vec<u8>,
+    pos: usize,
+    metrics: HashMap<RawMetricId, MetricInfo>,
+    strings: Vec<String>,
+    last_timestamp: i64,
+}
+
+impl BinaryReader {
+    /// Opens a binary file and reads its header.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open binary file {}", path.display()))?;
+        Self::new(BufReader::new(file)).with_context(|| format!("invalid binary file {}", path.display()))
+    }
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Reads the header of the format from `reader`, and returns a reader of points.
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut header = [0u8; MAGIC.len() + 1];
+        reader.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(anyhow!(
+                "bad magic bytes, this is not a binary file of the example plugin"
+            ));
+        }
+        let flags = header[MAGIC.len()];
+        Ok(Self {
+            reader,
+            compressed: flags & FLAG_ZSTD != 0,
+            frame: Vec::new(),
+            pos: 0,
+            metrics: HashMap::new(),
+            strings: Vec::new(),
+            last_timestamp: 0,
+        })
+    }
+
+    /// The metrics that have been defined so far, by id.
+    pub fn metrics(&self) -> &HashMap<RawMetricId, MetricInfo> {
+        &self.metrics
+    }
+
+    /// Decodes the next point, or returns `None` at the end of the file.
+    pub fn next_point(&mut self) -> anyhow::Result<Option<MeasurementPoint>> {
+        loop {
+            if self.pos == self.frame.len() && !self.read_frame()? {
+                return Ok(None);
+            }
+            let mut rec = Cursor {
+                buf: &self.frame,
+                pos: self.pos,
+            };
+            let tag = rec.u8()?;
+            match tag {
+                TAG_METRIC => {
+                    let id = RawMetricId::from_u64(rec.varint()?);
+                    let name = rec.string()?;
+                    let unit = rec.string()?;
+                    let value_type = match rec.u8()? {
+                        VALUE_U64 => WrappedMeasurementType::U64,
+                        VALUE_F64 => WrappedMeasurementType::F64,
+                        t => return Err(anyhow!("invalid value type {t}")),
+                    };
+                    self.pos = rec.pos;
+                    self.metrics.insert(id, MetricInfo { name, unit, value_type });
+                }
+                TAG_STRING => {
+                    let s = rec.string()?;
+                    self.pos = rec.pos;
+                    self.strings.push(s);
+                }
+                TAG_POINT => {
+                    let (point, timestamp) = self.decode_point(&mut rec)?;
+                    self.pos = rec.pos;
+                    self.last_timestamp = timestamp;
+                    return Ok(Some(point));
+                }
+                t => return Err(anyhow!("invalid record tag {t}")),
+            }
+        }
+    }
+
+    /// Decodes a point, and returns it with its timestamp in nanoseconds.
+    fn decode_point(&self, rec: &mut Cursor) -> anyhow::Result<(MeasurementPoint, i64)> {
+        let metric = RawMetricId::from_u64(rec.varint()?);
+        let timestamp = self.last_timestamp.wrapping_add(rec.signed_varint()?);
+        let info = self
+            .metrics
+            .get(&metric)
+            .with_context(|| format!("point of undefined metric {}", metric.as_u64()))?;
+        let value = match info.value_type {
+            WrappedMeasurementType::U64 => WrappedMeasurementValue::U64(rec.varint()?),
+            WrappedMeasurementType::F64 => WrappedMeasurementValue::F64(rec.f64()?),
+        };
+        let resource_kind = self.string(rec.varint()?)?;
+        let resource_id = self.string(rec.varint()?)?;
+        let consumer_kind = self.string(rec.varint()?)?;
+        let consumer_id = self.string(rec.varint()?)?;
+        let resource = Resource::parse(resource_kind.to_owned(), resource_id.to_owned())?;
+        let consumer = ResourceConsumer::parse(consumer_kind.to_owned(), consumer_id.to_owned())?;
+
+        let mut point =
+            MeasurementPoint::new_untyped(timestamp_from_nanos(timestamp), metric, resource, consumer, value);
+        let n_attributes = rec.varint()?;
+        for _ in 0..n_attributes {
+            let key = self.string(rec.varint()?)?.to_owned();
+            let value = match rec.u8()? {
+                ATTR_U64 => AttributeValue::U64(rec.varint()?),
+                ATTR_F64 => AttributeValue::F64(rec.f64()?),
+                ATTR_BOOL => AttributeValue::Bool(rec.u8()? != 0),
+                ATTR_STRING => AttributeValue::String(rec.string()?),
+                t => return Err(anyhow!("invalid attribute type {t}")),
+            };
+            point = point.with_attr(key, value);
+        }
+        Ok((point, timestamp))
+    }
+
+    fn string(&self, index: u64) -> anyhow::Result<&str> {
+        self.strings
+            .get(index as usize)
+            .map(String::as_str)
+            .with_context(|| format!("undefined string {index}"))
+    }
+
+    /// Reads the next frame, and returns false at the end of the file.
+    fn read_frame(&mut self) -> anyhow::Result<bool> {
+        let len = match read_varint_from(&mut self.reader)? {
+            Some(len) => len,
+            None => return Ok(false),
+        };
+        // Do not trust the length to allocate the frame: a corrupted file would allocate up to 2^64 bytes.
+        let mut frame = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut frame)?;
+        if frame.len() as u64 != len {
+            return Err(anyhow!("truncated frame"));
+        }
+        if self.compressed {
+            frame = zstd::decode_all(frame.as_slice()).context("invalid zstd frame")?;
+        }
+        self.frame = frame;
+        self.pos = 0;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = anyhow::Result<MeasurementPoint>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_point().transpose()
+    }
+}
+
+/// Reads the records of a decoded frame.
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn bytes(&mut self, n: usize) -> anyhow::Result<&[u8]> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.buf.len());
+        let end = end.context("truncated record")?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn f64(&mut self) -> anyhow::Result<f64> {
+        let bytes = self.bytes(8)?;
+        Ok(f64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut remaining = &self.buf[self.pos..];
+        let before = remaining.len();
+        let value = read_varint_from(&mut remaining)?.context("truncated record")?;
+        self.pos += before - remaining.len();
+        Ok(value)
+    }
+
+    fn signed_varint(&mut self) -> anyhow::Result<i64> {
+        let n = self.varint()?;
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = usize::try_from(self.varint()?)?;
+        let bytes = self.bytes(len)?;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+/// Reads a varint, or returns `None` if the reader is at the end.
+fn read_varint_from(reader: &mut impl Read) -> anyhow::Result<Option<u64>> {
+    let mut value: u64 = 0;
+    for i in 0..10 {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            return match i {
+                0 => Ok(None),
+                _ => Err(anyhow!("truncated varint")),
+            };
+        }
+        value |= u64::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(anyhow!("varint too long"))
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_signed_varint(buf: &mut Vec<u8>, value: i64) {
+    write_varint(buf, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    write_varint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn timestamp_from_nanos(nanos: i64) -> Timestamp {
+    let t = if nanos >= 0 {
+        UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_nanos(nanos.unsigned_abs())
+    };
+    Timestamp::from(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use alumet::measurement::Timestamp;
+
+    use super::*;
+
+    fn metric_type(id: &RawMetricId) -> anyhow::Result<MetricInfo> {
+        match id.as_u64() {
+            0 => Ok(MetricInfo {
+                name: String::from("counter"),
+                unit: String::from("unity"),
+                value_type: WrappedMeasurementType::U64,
+            }),
+            1 => Ok(MetricInfo {
+                name: String::from("energy"),
+                unit: String::from("joule"),
+                value_type: WrappedMeasurementType::F64,
+            }),
+            _ => Err(anyhow!("unregistered metric id: {}", id.as_u64())),
+        }
+    }
+
+    fn point(metric: u64, nanos: i64, value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            timestamp_from_nanos(nanos),
+            RawMetricId::from_u64(metric),
+            Resource::CpuPackage { id: 0 },
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    fn buffer(points: Vec<MeasurementPoint>) -> MeasurementBuffer {
+        let mut buffer = MeasurementBuffer::new();
+        for p in points {
+            buffer.push(p);
+        }
+        buffer
+    }
+
+    /// Returns the fields of a point, in a comparable form.
+    #[allow(clippy::type_complexity)]
+    fn fields(
+        m: &MeasurementPoint,
+    ) -> (
+        Timestamp,
+        u64,
+        String,
+        String,
+        String,
+        String,
+        String,
+        Vec<(String, String)>,
+    ) {
+        (
+            m.timestamp,
+            m.metric.as_u64(),
+            format!("{:?}", m.value),
+            m.resource.kind().to_owned(),
+            m.resource.id_display().to_string(),
+            m.consumer.kind().to_owned(),
+            m.consumer.id_display().to_string(),
+            m.attributes()
+                .map(|(k, v)| {
+                    // The string attributes, owned or not, are all decoded as owned strings.
+                    let v = match v {
+                        AttributeValue::U64(_) | AttributeValue::F64(_) | AttributeValue::Bool(_) => format!("{v:?}"),
+                        other => format!("String({:?})", other.to_string()),
+                    };
+                    (k.to_owned(), v)
+                })
+                .collect(),
+        )
+    }
+
+    fn read_all(bytes: &[u8]) -> anyhow::Result<Vec<MeasurementPoint>> {
+        BinaryReader::new(bytes)?.collect()
+    }
+
+    fn round_trip(zstd_level: Option<i32>) {
+        let frames = vec![
+            vec![
+                point(0, 1_000, WrappedMeasurementValue::U64(u64::MAX)).with_attr("domain", "package"),
+                point(1, 2_000, WrappedMeasurementValue::F64(-3.0))
+                    .with_attr("count", AttributeValue::U64(2))
+                    .with_attr("ratio", AttributeValue::F64(0.5))
+                    .with_attr("ok", AttributeValue::Bool(true)),
+            ],
+            // The second frame reuses the definitions of the first one, and goes back in time.
+            vec![
+                point(0, 500, WrappedMeasurementValue::U64(1)).with_attr("domain", "package"),
+                point(1, -1, WrappedMeasurementValue::F64(f64::INFINITY)),
+            ],
+        ];
+        let mut output = BinaryOutput::new(Vec::new(), zstd_level).unwrap();
+        for frame in &frames {
+            output.write_frame(&buffer(frame.clone()), metric_type).unwrap();
+        }
+        let bytes = output.into_inner();
+        assert_eq!(&bytes[..MAGIC.len()], MAGIC);
+
+        let mut reader = BinaryReader::new(bytes.as_slice()).unwrap();
+        let decoded: Vec<MeasurementPoint> = (&mut reader).collect::<anyhow::Result<_>>().unwrap();
+        let expected: Vec<_> = frames.iter().flatten().map(fields).collect();
+        assert_eq!(decoded.iter().map(fields).collect::<Vec<_>>(), expected);
+        assert_eq!(
+            reader.metrics()[&RawMetricId::from_u64(1)],
+            metric_type(&RawMetricId::from_u64(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn round_trip_without_compression() {
+        round_trip(None);
+    }
+
+    #[test]
+    fn round_trip_with_zstd() {
+        round_trip(Some(3));
+    }
+
+    #[test]
+    fn a_frame_that_fails_defines_nothing() {
+        for zstd_level in [None, Some(3)] {
+            let mut output = BinaryOutput::new(Vec::new(), zstd_level).unwrap();
+            // The first point defines the metric and some strings, then the second one has the wrong type.
+            let invalid = buffer(vec![
+                point(0, 1_000, WrappedMeasurementValue::U64(1)).with_attr("domain", "package"),
+                point(0, 2_000, WrappedMeasurementValue::F64(1.0)),
+            ]);
+            assert!(output.write_frame(&invalid, metric_type).is_err());
+            // An unregistered metric is an error too.
+            assert!(output
+                .write_frame(&buffer(vec![point(2, 0, WrappedMeasurementValue::U64(1))]), metric_type)
+                .is_err());
+
+            let valid = vec![point(0, 3_000, WrappedMeasurementValue::U64(2)).with_attr("domain", "package")];
+            output.write_frame(&buffer(valid.clone()), metric_type).unwrap();
+            let decoded = read_all(&output.into_inner()).unwrap();
+            assert_eq!(
+                decoded.iter().map(fields).collect::<Vec<_>>(),
+                valid.iter().map(fields).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn reject_other_files() {
+        assert!(BinaryReader::new(&b"ALUMETB0\0"[..]).is_err());
+        assert!(BinaryReader::new(&b"ALU"[..]).is_err());
+    }
+
+    #[test]
+    fn reject_a_truncated_frame() {
+        let mut file = MAGIC.to_vec();
+        file.push(0);
+        // The length of the frame is huge, but the file ends after a few bytes.
+        write_varint(&mut file, u64::MAX);
+        file.extend_from_slice(&[TAG_STRING, 1, b'a']);
+        let err = read_all(&file).unwrap_err();
+        assert_eq!(err.to_string(), "truncated frame");
+    }
+}
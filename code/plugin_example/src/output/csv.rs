@@ -0,0 +1,269 @@
+//! CSV format for the output, as specified by RFC 4180.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use alumet::measurement::{MeasurementPoint, WrappedMeasurementValue};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use super::{format_timestamp, TimestampFormat};
+
+/// Options of the CSV format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvOptions {
+    /// How to write the timestamps.
+    pub timestamp: TimestampFormat,
+    /// How to write the attributes.
+    pub attributes: AttributeColumns,
+}
+
+/// How to write the attributes of the measurement points.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum AttributeColumns {
+    /// All the attributes are serialized in a single column, `attributes`, as `key=value` pairs separated by `;`.
+    Single,
+    /// Each attribute of `keys` gets its own column, named after the key.
+    /// The other attributes are serialized in the `attributes` column.
+    Flatten { keys: Vec<String> },
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            timestamp: TimestampFormat::Rfc3339,
+            attributes: AttributeColumns::Single,
+        }
+    }
+}
+
+/// Line terminator required by RFC 4180.
+const CRLF: &str = "\r\n";
+
+/// The columns that are always present: the flattened attributes come after them, followed by `attributes`.
+const COLUMNS: [&str; 7] = [
+    "timestamp",
+    "metric",
+    "value",
+    "resource_kind",
+    "resource_id",
+    "consumer_kind",
+    "consumer_id",
+];
+
+/// The column of the attributes that are not flattened.
+const ATTRIBUTES_COLUMN: &str = "attributes";
+
+impl CsvOptions {
+    /// Checks the options: the flattened attributes must have unique names, which differ from the other columns.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if let AttributeColumns::Flatten { keys } = &self.attributes {
+            let mut columns: HashSet<&str> = COLUMNS.into_iter().chain([ATTRIBUTES_COLUMN]).collect();
+            for key in keys {
+                if !columns.insert(key) {
+                    return Err(anyhow!(
+                        "invalid CSV options: the attribute {key} cannot be flattened, there is already a column with this name"
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the header of the CSV file.
+    ///
+    /// The columns only depend on the options, so that the header is the same for every file.
+    pub fn header(&self) -> String {
+        let mut columns = COLUMNS.to_vec();
+        if let AttributeColumns::Flatten { keys } = &self.attributes {
+            columns.extend(keys.iter().map(String::as_str));
+        }
+        columns.push(ATTRIBUTES_COLUMN);
+
+        let mut header = columns.into_iter().map(escape).collect::<Vec<_>>().join(",");
+        header.push_str(CRLF);
+        header
+    }
+
+    /// Formats a measurement point as a CSV record, terminated by CRLF.
+    pub fn record(&self, m: &MeasurementPoint, metric_name: &str) -> String {
+        let value = match m.value {
+            WrappedMeasurementValue::F64(x) => x.to_string(),
+            WrappedMeasurementValue::U64(x) => x.to_string(),
+        };
+        let mut fields: Vec<String> = vec![
+            format_timestamp(m.timestamp, self.timestamp),
+            escape(metric_name).into_owned(),
+            value,
+            escape(m.resource.kind()).into_owned(),
+            escape(&m.resource.id_display().to_string()).into_owned(),
+            escape(m.consumer.kind()).into_owned(),
+            escape(&m.consumer.id_display().to_string()).into_owned(),
+        ];
+
+        // Split the attributes between the dedicated columns and the `attributes` column.
+        let mut remaining = Vec::new();
+        match &self.attributes {
+            AttributeColumns::Single => {
+                remaining.extend(m.attributes().map(|(key, value)| (key.to_string(), value.to_string())));
+            }
+            AttributeColumns::Flatten { keys } => {
+                let mut columns = vec![String::new(); keys.len()];
+                for (key, value) in m.attributes() {
+                    match keys.iter().position(|k| k == key) {
+                        Some(i) => columns[i] = value.to_string(),
+                        None => remaining.push((key.to_string(), value.to_string())),
+                    }
+                }
+                fields.extend(columns.iter().map(|c| escape(c).into_owned()));
+            }
+        }
+        let attributes = remaining
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape_attribute(key), escape_attribute(value)))
+            .collect::<Vec<_>>()
+            .join(";");
+        fields.push(escape(&attributes).into_owned());
+
+        let mut record = fields.join(",");
+        record.push_str(CRLF);
+        record
+    }
+}
+
+/// Escapes a CSV field according to RFC 4180.
+///
+/// Fields that contain a comma, a double quote or a line break are enclosed in double quotes,
+/// and the double quotes inside the field are doubled.
+fn escape(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Escapes the key or value of an attribute, so that the `attributes` column can be parsed back.
+///
+/// The separators `;` and `=` are prefixed with a backslash, as is the backslash itself.
+fn escape_attribute(s: &str) -> Cow<'_, str> {
+    if s.contains(['\\', ';', '=']) {
+        let mut res = String::with_capacity(s.len() + 2);
+        for c in s.chars() {
+            if matches!(c, '\\' | ';' | '=') {
+                res.push('\\');
+            }
+            res.push(c);
+        }
+        Cow::Owned(res)
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::{AttributeValue, Timestamp};
+    use alumet::metrics::RawMetricId;
+    use alumet::resources::{Resource, ResourceConsumer};
+
+    use super::*;
+
+    fn point() -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_millis(1500)),
+            RawMetricId::from_u64(0),
+            Resource::CpuCore { id: 3 },
+            ResourceConsumer::Process { pid: 42 },
+            WrappedMeasurementValue::F64(2.5),
+        )
+    }
+
+    fn flatten(keys: &[&str]) -> CsvOptions {
+        CsvOptions {
+            attributes: AttributeColumns::Flatten {
+                keys: keys.iter().map(|k| k.to_string()).collect(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn quote_the_special_characters() {
+        let m = point()
+            .with_attr("comma", AttributeValue::Str("a,b"))
+            .with_attr("quote", AttributeValue::Str("say \"hi\""))
+            .with_attr("lines", AttributeValue::Str("a\r\nb"))
+            .with_attr("key;=\"", AttributeValue::Str("x=y;z"));
+        let record = CsvOptions::default().record(&m, "energy,total");
+        assert_eq!(
+            record,
+            "1970-01-01T00:00:01.500000000Z,\"energy,total\",2.5,cpu_core,3,process,42,\
+             \"comma=a,b;quote=say \"\"hi\"\";lines=a\r\nb;key\\;\\=\"\"=x\\=y\\;z\"\r\n"
+        );
+
+        // The names of the flattened attributes are escaped in the header.
+        let options = flatten(&["a,b", "say \"hi\"", "x\ny"]);
+        assert_eq!(
+            options.header(),
+            "timestamp,metric,value,resource_kind,resource_id,consumer_kind,consumer_id,\
+             \"a,b\",\"say \"\"hi\"\"\",\"x\ny\",attributes\r\n"
+        );
+        let m = point().with_attr("x\ny", AttributeValue::Str("1,2"));
+        assert!(options.record(&m, "m").ends_with(",,,\"1,2\",\r\n"));
+    }
+
+    #[test]
+    fn header_does_not_depend_on_the_points() {
+        let options = CsvOptions::default();
+        assert_eq!(
+            options.header(),
+            "timestamp,metric,value,resource_kind,resource_id,consumer_kind,consumer_id,attributes\r\n"
+        );
+        let options = flatten(&["cpu", "state"]);
+        let header = options.header();
+        assert_eq!(
+            header,
+            "timestamp,metric,value,resource_kind,resource_id,consumer_kind,consumer_id,cpu,state,attributes\r\n"
+        );
+        // Every record has as many fields as the header, whatever its attributes.
+        for m in [point(), point().with_attr("state", "user").with_attr("other", 1u64)] {
+            assert_eq!(options.record(&m, "m").split(',').count(), header.split(',').count());
+        }
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let rfc3339 = CsvOptions::default().record(&point(), "m");
+        assert!(rfc3339.starts_with("1970-01-01T00:00:01.500000000Z,m,"), "{rfc3339}");
+        let options = CsvOptions {
+            timestamp: TimestampFormat::EpochNanos,
+            ..Default::default()
+        };
+        let nanos = options.record(&point(), "m");
+        assert!(nanos.starts_with("1500000000,m,"), "{nanos}");
+    }
+
+    #[test]
+    fn flattened_or_single_attributes() {
+        let m = point().with_attr("state", "user").with_attr("cpu", 3u64);
+        let single = CsvOptions::default().record(&m, "m");
+        assert!(single.ends_with(",state=user;cpu=3\r\n"), "{single}");
+        // The flattened attributes follow the order of the keys, the others are in the last column.
+        let flattened = flatten(&["cpu", "missing"]).record(&m, "m");
+        assert!(flattened.ends_with(",3,,state=user\r\n"), "{flattened}");
+    }
+
+    #[test]
+    fn reject_invalid_flattened_keys() {
+        flatten(&["cpu", "state"]).check().unwrap();
+        CsvOptions::default().check().unwrap();
+        for keys in [&["cpu", "cpu"][..], &["value"], &["consumer_id"], &["attributes"]] {
+            let err = flatten(keys).check().unwrap_err();
+            assert!(err.to_string().contains("already a column"), "{keys:?}: {err}");
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! JSON Lines format for the output: one JSON object per measurement point.
+//!
+//! See <https://jsonlines.org>.
+
+use alumet::measurement::{MeasurementPoint, WrappedMeasurementType, WrappedMeasurementValue};
+use alumet::metrics::Metric;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::{format_timestamp, unix_nanos, TimestampFormat};
+
+/// Options of the JSON Lines format.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonLinesOptions {
+    /// How to write the timestamps: as a RFC 3339 string, or as a number of nanoseconds.
+    pub timestamp: TimestampFormat,
+}
+
+impl Default for JsonLinesOptions {
+    fn default() -> Self {
+        Self {
+            timestamp: TimestampFormat::Rfc3339,
+        }
+    }
+}
+
+/// A measurement point with the definition of its metric, ready to be serialized.
+#[derive(Serialize)]
+struct JsonPoint<'a> {
+    timestamp: Value,
+    metric: &'a str,
+    unit: String,
+    value_type: &'static str,
+    value: Value,
+    resource_kind: &'a str,
+    resource_id: String,
+    consumer_kind: &'a str,
+    consumer_id: String,
+    attributes: Map<String, Value>,
+}
+
+impl JsonLinesOptions {
+    /// Formats a measurement point as a JSON object, on a single line terminated by `\n`.
+    ///
+    /// The metric must be the definition of the metric of the point.
+    pub fn record(&self, m: &MeasurementPoint, metric: &Metric) -> serde_json::Result<String> {
+        let timestamp = match self.timestamp {
+            TimestampFormat::Rfc3339 => Value::from(format_timestamp(m.timestamp, self.timestamp)),
+            // Nanoseconds since the epoch fit in a i64 until the year 2262.
+            TimestampFormat::EpochNanos => Value::from(i64::try_from(unix_nanos(m.timestamp)).unwrap_or(i64::MAX)),
+        };
+        let value_type = match metric.value_type {
+            WrappedMeasurementType::F64 => "f64",
+            WrappedMeasurementType::U64 => "u64",
+        };
+        // Note: JSON cannot represent NaN and infinite numbers, they become `null`.
+        let value = match m.value {
+            WrappedMeasurementValue::F64(x) => Value::from(x),
+            WrappedMeasurementValue::U64(x) => Value::from(x),
+        };
+        let attributes = m
+            .attributes()
+            .map(|(key, value)| (key.to_string(), Value::from(value.to_string())))
+            .collect();
+
+        let point = JsonPoint {
+            timestamp,
+            metric: &metric.name,
+            unit: metric.unit.unique_name().to_string(),
+            value_type,
+            value,
+            resource_kind: m.resource.kind(),
+            resource_id: m.resource.id_display().to_string(),
+            consumer_kind: m.consumer.kind(),
+            consumer_id: m.consumer.id_display().to_string(),
+            attributes,
+        };
+        let mut line = serde_json::to_string(&point)?;
+        line.push('\n');
+        Ok(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::{AttributeValue, Timestamp};
+    use alumet::metrics::RawMetricId;
+    use alumet::resources::{Resource, ResourceConsumer};
+    use alumet::units::{PrefixedUnit, Unit};
+    use serde_json::json;
+
+    use super::*;
+
+    fn metric(value_type: WrappedMeasurementType) -> Metric {
+        Metric {
+            name: String::from("energy"),
+            description: String::from("energy consumed"),
+            value_type,
+            unit: PrefixedUnit::from(Unit::Joule),
+        }
+    }
+
+    fn point(value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_millis(1500)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 1 },
+            ResourceConsumer::Process { pid: 42 },
+            value,
+        )
+    }
+
+    fn parse(line: &str) -> Value {
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.lines().count(), 1, "one point per line: {line}");
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn every_field_is_written() {
+        let m = point(WrappedMeasurementValue::U64(u64::MAX))
+            .with_attr("quote \" and \\", AttributeValue::Str("line\nbreak\ttab"))
+            .with_attr("unicode", AttributeValue::Str("é ✓"))
+            .with_attr("number", 12u64);
+        let line = JsonLinesOptions::default()
+            .record(&m, &metric(WrappedMeasurementType::U64))
+            .unwrap();
+        assert_eq!(
+            parse(&line),
+            json!({
+                "timestamp": "1970-01-01T00:00:01.500000000Z",
+                "metric": "energy",
+                "unit": "J",
+                "value_type": "u64",
+                "value": u64::MAX,
+                "resource_kind": "cpu_package",
+                "resource_id": "1",
+                "consumer_kind": "process",
+                "consumer_id": "42",
+                "attributes": {
+                    "quote \" and \\": "line\nbreak\ttab",
+                    "unicode": "é ✓",
+                    "number": "12",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn float_values_and_timestamps_in_nanoseconds() {
+        let options = JsonLinesOptions {
+            timestamp: TimestampFormat::EpochNanos,
+        };
+        let metric = metric(WrappedMeasurementType::F64);
+        let line = options
+            .record(&point(WrappedMeasurementValue::F64(0.25)), &metric)
+            .unwrap();
+        let json = parse(&line);
+        assert_eq!(json["timestamp"], json!(1_500_000_000));
+        assert_eq!(json["value_type"], json!("f64"));
+        assert_eq!(json["value"], json!(0.25));
+        assert_eq!(json["attributes"], json!({}));
+
+        // JSON has no NaN.
+        let line = options
+            .record(&point(WrappedMeasurementValue::F64(f64::NAN)), &metric)
+            .unwrap();
+        assert_eq!(parse(&line)["value"], Value::Null);
+    }
+}
@@ -0,0 +1,130 @@
+//! InfluxDB line protocol format for the output.
+//!
+//! See <https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/>.
+
+use std::borrow::Cow;
+
+use alumet::measurement::{MeasurementPoint, WrappedMeasurementValue};
+
+use super::{attribute_key, unix_nanos};
+
+/// Name of the field that contains the measured value.
+const VALUE_FIELD: &str = "value";
+
+/// Formats a measurement point as a line of the InfluxDB line protocol, terminated by `\n`.
+///
+/// The metric becomes the measurement, the resource, consumer and attributes become tags,
+/// and the value becomes the `value` field. The timestamp is in nanoseconds.
+/// The attributes whose key is already used by the resource or consumer tags are renamed, see [`attribute_key`].
+///
+/// Returns `None` if the value cannot be represented in the line protocol (NaN or infinite floats).
+pub fn record(m: &MeasurementPoint, metric_name: &str) -> Option<String> {
+    let resource_id = m.resource.id_display().to_string();
+    let consumer_id = m.consumer.id_display().to_string();
+    let mut tags: Vec<(Cow<str>, Cow<str>)> = vec![
+        (Cow::Borrowed("resource_kind"), Cow::Borrowed(m.resource.kind())),
+        (Cow::Borrowed("resource_id"), Cow::Owned(resource_id)),
+        (Cow::Borrowed("consumer_kind"), Cow::Borrowed(m.consumer.kind())),
+        (Cow::Borrowed("consumer_id"), Cow::Owned(consumer_id)),
+    ];
+    tags.extend(m.attributes().map(|(key, value)| {
+        (
+            Cow::Owned(attribute_key(key).into_owned()),
+            Cow::Owned(value.to_string()),
+        )
+    }));
+    // InfluxDB recommends to sort the tags by key, and does not accept empty tag values.
+    tags.retain(|(_, value)| !value.is_empty());
+    tags.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let value = match m.value {
+        WrappedMeasurementValue::F64(x) if !x.is_finite() => return None,
+        WrappedMeasurementValue::F64(x) => x.to_string(),
+        // Integers are signed in the line protocol: use the unsigned type (InfluxDB 2+) for the values that do not fit.
+        WrappedMeasurementValue::U64(x) if x > i64::MAX as u64 => format!("{x}u"),
+        WrappedMeasurementValue::U64(x) => format!("{x}i"),
+    };
+
+    let mut line = escape(metric_name, &[',', ' ']).into_owned();
+    for (key, value) in &tags {
+        line.push(',');
+        line.push_str(&escape(key, &[',', '=', ' ']));
+        line.push('=');
+        line.push_str(&escape(value, &[',', '=', ' ']));
+    }
+    line.push(' ');
+    line.push_str(VALUE_FIELD);
+    line.push('=');
+    line.push_str(&value);
+    line.push(' ');
+    line.push_str(&unix_nanos(m.timestamp).to_string());
+    line.push('\n');
+    Some(line)
+}
+
+/// Escapes the `special` characters with a backslash.
+///
+/// Line breaks are not supported by the line protocol, they are replaced by `\n`.
+fn escape<'a>(s: &'a str, special: &[char]) -> Cow<'a, str> {
+    if !s.contains(|c| special.contains(&c) || c == '\n') {
+        return Cow::Borrowed(s);
+    }
+    let mut res = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        if c == '\n' {
+            res.push_str("\\n");
+            continue;
+        }
+        if special.contains(&c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    Cow::Owned(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::{MeasurementPoint, Timestamp, WrappedMeasurementValue};
+    use alumet::metrics::RawMetricId;
+    use alumet::resources::{Resource, ResourceConsumer};
+
+    use super::record;
+
+    fn point(value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(1)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 0 },
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    #[test]
+    fn format_a_point() {
+        let m = point(WrappedMeasurementValue::U64(42)).with_attr("domain name", "package,0");
+        assert_eq!(
+            record(&m, "example metric").unwrap(),
+            "example\\ metric,consumer_kind=local_machine,domain\\ name=package\\,0,\
+             resource_id=0,resource_kind=cpu_package value=42i 1000000000\n"
+        );
+    }
+
+    #[test]
+    fn attributes_do_not_replace_the_builtin_tags() {
+        let m = point(WrappedMeasurementValue::F64(1.5)).with_attr("resource_kind", "battery");
+        assert_eq!(
+            record(&m, "example").unwrap(),
+            "example,attr_resource_kind=battery,consumer_kind=local_machine,\
+             resource_id=0,resource_kind=cpu_package value=1.5 1000000000\n"
+        );
+    }
+
+    #[test]
+    fn skip_non_finite_values() {
+        assert_eq!(record(&point(WrappedMeasurementValue::F64(f64::NAN)), "example"), None);
+    }
+}
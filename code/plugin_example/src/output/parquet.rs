@@ -0,0 +1,339 @@
+//! Output that writes the measurements to an Apache Parquet file, for offline analysis.
+//!
+//! The measurement points are accumulated in Arrow record batches, which are written as Parquet row groups
+//! when they become big enough, or old enough. The footer of the file is written when the output stops.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use alumet::measurement::{MeasurementBuffer, WrappedMeasurementValue};
+use alumet::metrics::{Metric, RawMetricId};
+use alumet::pipeline::elements::error::WriteError;
+use alumet::pipeline::elements::output::OutputContext;
+use alumet::pipeline::Output;
+use anyhow::Context;
+use arrow_array::builder::{Float64Builder, MapBuilder, StringBuilder, TimestampNanosecondBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, FieldRef, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::{Deserialize, Serialize};
+
+use super::unix_nanos;
+
+/// Configuration of the Parquet output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParquetConfig {
+    /// If true, the measurements are written to the Parquet file.
+    pub enabled: bool,
+    /// Path of the Parquet file. It is overwritten if it exists.
+    pub path: PathBuf,
+    /// Maximum number of measurement points in a row group.
+    pub max_rows_per_group: usize,
+    /// Maximum delay before the accumulated points are written as a row group.
+    #[serde(with = "humantime_serde")]
+    pub max_group_delay: Duration,
+}
+
+impl Default for ParquetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("alumet-tutorial-output.parquet"),
+            max_rows_per_group: 100_000,
+            max_group_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Writes the measurements to a Parquet file.
+pub struct ParquetOutput {
+    /// The Parquet writer, `None` once closed.
+    writer: Option<ArrowWriter<File>>,
+    schema: SchemaRef,
+    batch: BatchBuilder,
+    max_rows_per_group: usize,
+    max_group_delay: Duration,
+    last_flush: Instant,
+}
+
+/// Returns the schema of the file.
+///
+/// Columns:
+/// - `timestamp`: timestamp in nanoseconds, UTC
+/// - `metric`: name of the metric
+/// - `value_u64` and `value_f64`: the measured value, in the column that corresponds to its type (the other is null)
+/// - `resource_kind`, `resource_id`, `consumer_kind`, `consumer_id`
+/// - `attributes`: map of the attributes, from key to value
+fn schema() -> SchemaRef {
+    let entries = Field::new_struct(
+        "entries",
+        vec![Arc::new(Field::new("keys", DataType::Utf8, false)), value_field()],
+        false,
+    );
+    Arc::new(Schema::new(vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("metric", DataType::Utf8, false),
+        Field::new("value_u64", DataType::UInt64, true),
+        Field::new("value_f64", DataType::Float64, true),
+        Field::new("resource_kind", DataType::Utf8, false),
+        Field::new("resource_id", DataType::Utf8, false),
+        Field::new("consumer_kind", DataType::Utf8, false),
+        Field::new("consumer_id", DataType::Utf8, false),
+        Field::new("attributes", DataType::Map(Arc::new(entries), false), false),
+    ]))
+}
+
+/// The field of the values of the `attributes` map.
+fn value_field() -> FieldRef {
+    Arc::new(Field::new("values", DataType::Utf8, false))
+}
+
+/// Builds a record batch, column by column, with the columns of [`schema`].
+struct BatchBuilder {
+    timestamp: TimestampNanosecondBuilder,
+    metric: StringBuilder,
+    value_u64: UInt64Builder,
+    value_f64: Float64Builder,
+    resource_kind: StringBuilder,
+    resource_id: StringBuilder,
+    consumer_kind: StringBuilder,
+    consumer_id: StringBuilder,
+    attributes: MapBuilder<StringBuilder, StringBuilder>,
+    len: usize,
+}
+
+impl ParquetOutput {
+    /// Creates the Parquet file at `path`.
+    pub fn create(config: &ParquetConfig) -> anyhow::Result<Self> {
+        let path: &Path = &config.path;
+        let file = File::create(path).with_context(|| format!("failed to create Parquet file {}", path.display()))?;
+
+        let schema = schema();
+        let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))
+            .with_context(|| format!("failed to write the Parquet schema to {}", path.display()))?;
+
+        Ok(Self {
+            writer: Some(writer),
+            batch: BatchBuilder::new(),
+            schema,
+            max_rows_per_group: config.max_rows_per_group,
+            max_group_delay: config.max_group_delay,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Writes the accumulated points as a new row group.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.last_flush = Instant::now();
+        if self.batch.len == 0 {
+            return Ok(());
+        }
+        let batch = std::mem::replace(&mut self.batch, BatchBuilder::new()).finish(self.schema.clone())?;
+        let writer = self.writer.as_mut().context("the Parquet file is closed")?;
+        writer.write(&batch)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the remaining points and the footer of the file.
+    fn close(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    /// Appends the measurements to the current batch. `metrics` returns the definition of a metric.
+    fn append_all<'a>(
+        &mut self,
+        measurements: &MeasurementBuffer,
+        metrics: impl Fn(&RawMetricId) -> Option<&'a Metric>,
+    ) -> anyhow::Result<()> {
+        for m in measurements.iter() {
+            let metric =
+                metrics(&m.metric).with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?;
+            let b = &mut self.batch;
+            b.timestamp
+                .append_value(i64::try_from(unix_nanos(m.timestamp)).unwrap_or(i64::MAX));
+            b.metric.append_value(&metric.name);
+            match m.value {
+                WrappedMeasurementValue::U64(x) => {
+                    b.value_u64.append_value(x);
+                    b.value_f64.append_null();
+                }
+                WrappedMeasurementValue::F64(x) => {
+                    b.value_u64.append_null();
+                    b.value_f64.append_value(x);
+                }
+            }
+            b.resource_kind.append_value(m.resource.kind());
+            b.resource_id.append_value(m.resource.id_display().to_string());
+            b.consumer_kind.append_value(m.consumer.kind());
+            b.consumer_id.append_value(m.consumer.id_display().to_string());
+            for (key, value) in m.attributes() {
+                b.attributes.keys().append_value(key);
+                b.attributes.values().append_value(value.to_string());
+            }
+            b.attributes.append(true).context("invalid attributes")?;
+            b.len += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Output for ParquetOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        self.append_all(measurements, |id| ctx.metrics.by_id(id))?;
+        if self.batch.len >= self.max_rows_per_group || self.last_flush.elapsed() >= self.max_group_delay {
+            self.flush().context("failed to write a Parquet row group")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParquetOutput {
+    fn drop(&mut self) {
+        // The output is dropped when it stops: finalize the file, otherwise it cannot be read.
+        if let Err(e) = self.close() {
+            log::error!("failed to finalize the Parquet file: {e:#}");
+        }
+    }
+}
+
+impl BatchBuilder {
+    fn new() -> Self {
+        Self {
+            timestamp: TimestampNanosecondBuilder::new().with_timezone("UTC"),
+            metric: StringBuilder::new(),
+            value_u64: UInt64Builder::new(),
+            value_f64: Float64Builder::new(),
+            resource_kind: StringBuilder::new(),
+            resource_id: StringBuilder::new(),
+            consumer_kind: StringBuilder::new(),
+            consumer_id: StringBuilder::new(),
+            attributes: MapBuilder::new(None, StringBuilder::new(), StringBuilder::new())
+                .with_values_field(value_field()),
+            len: 0,
+        }
+    }
+
+    /// Builds the batch. Its columns must be in the order of `schema`.
+    fn finish(mut self, schema: SchemaRef) -> anyhow::Result<RecordBatch> {
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.metric.finish()),
+            Arc::new(self.value_u64.finish()),
+            Arc::new(self.value_f64.finish()),
+            Arc::new(self.resource_kind.finish()),
+            Arc::new(self.resource_id.finish()),
+            Arc::new(self.consumer_kind.finish()),
+            Arc::new(self.consumer_id.finish()),
+            Arc::new(self.attributes.finish()),
+        ];
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::UNIX_EPOCH;
+
+    use alumet::measurement::{MeasurementPoint, Timestamp, WrappedMeasurementType};
+    use alumet::resources::{Resource, ResourceConsumer};
+    use alumet::units::Unit;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, UInt64Type};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+
+    fn metric(name: &str, value_type: WrappedMeasurementType) -> Metric {
+        Metric {
+            name: name.to_owned(),
+            description: String::new(),
+            value_type,
+            unit: Unit::Unity.into(),
+        }
+    }
+
+    fn point(metric: u64, secs: u64, value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(secs)),
+            RawMetricId::from_u64(metric),
+            Resource::CpuPackage { id: 0 },
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ParquetConfig {
+            enabled: true,
+            path: dir.path().join("out.parquet"),
+            ..Default::default()
+        };
+        let metrics = HashMap::from([
+            (RawMetricId::from_u64(0), metric("counter", WrappedMeasurementType::U64)),
+            (RawMetricId::from_u64(1), metric("energy", WrappedMeasurementType::F64)),
+        ]);
+        {
+            let mut output = ParquetOutput::create(&config).unwrap();
+            let mut buffer = MeasurementBuffer::new();
+            buffer.push(point(0, 1, WrappedMeasurementValue::U64(7)).with_attr("domain", "package"));
+            buffer.push(point(1, 1, WrappedMeasurementValue::F64(2.5)));
+            output.append_all(&buffer, |id| metrics.get(id)).unwrap();
+            output.flush().unwrap();
+
+            let mut buffer = MeasurementBuffer::new();
+            buffer.push(point(1, 2, WrappedMeasurementValue::F64(-3.0)));
+            output.append_all(&buffer, |id| metrics.get(id)).unwrap();
+            // Dropping the output writes the last row group and the footer.
+        }
+
+        let file = File::open(&config.path).unwrap();
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 2);
+        assert_eq!(builder.schema(), &schema());
+        let batches: Vec<RecordBatch> = builder.build().unwrap().collect::<Result<_, _>>().unwrap();
+        let column = |name: &'static str| batches.iter().map(move |b| b.column_by_name(name).unwrap().clone());
+
+        let metric: Vec<String> = column("metric")
+            .flat_map(|c| {
+                c.as_string::<i32>()
+                    .iter()
+                    .flatten()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(metric, ["counter", "energy", "energy"]);
+        let value_u64: Vec<Option<u64>> = column("value_u64")
+            .flat_map(|c| c.as_primitive::<UInt64Type>().iter().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(value_u64, [Some(7), None, None]);
+        let value_f64: Vec<Option<f64>> = column("value_f64")
+            .flat_map(|c| c.as_primitive::<Float64Type>().iter().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(value_f64, [None, Some(2.5), Some(-3.0)]);
+
+        // The attributes of the first point, which is in the first batch.
+        let attributes = batches[0].column_by_name("attributes").unwrap().as_map().clone();
+        assert_eq!(attributes.value_length(0), 1);
+        assert_eq!(attributes.keys().as_string::<i32>().value(0), "domain");
+        assert_eq!(attributes.values().as_string::<i32>().value(0), "package");
+        assert_eq!(attributes.value_length(1), 0);
+    }
+}
@@ -0,0 +1,487 @@
+//! Output that exposes the latest measurements on a HTTP endpoint, in the Prometheus and OpenMetrics text formats.
+//!
+//! See <https://prometheus.io/docs/instrumenting/exposition_formats/> and <https://openmetrics.io>.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use alumet::measurement::{MeasurementBuffer, MeasurementPoint, WrappedMeasurementValue};
+use alumet::metrics::Metric;
+use alumet::pipeline::elements::error::WriteError;
+use alumet::pipeline::elements::output::OutputContext;
+use alumet::pipeline::Output;
+use alumet::units::{Unit, UnitPrefix};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::attribute_key;
+
+/// Configuration of the Prometheus endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrometheusConfig {
+    /// If true, the endpoint is started.
+    pub enabled: bool,
+    /// Address to listen on.
+    pub address: SocketAddr,
+    /// Series that are not updated for this long are removed from the endpoint.
+    #[serde(with = "humantime_serde")]
+    pub staleness: Duration,
+    /// Names of the metrics that are counters (their value only grows). The other metrics are gauges.
+    pub counters: Vec<String>,
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: SocketAddr::from(([127, 0, 0, 1], 9091)),
+            staleness: Duration::from_secs(300),
+            counters: vec![String::from("example_source_call_counter")],
+        }
+    }
+}
+
+/// Keeps the latest value of every series and serves them on `/metrics`.
+pub struct PrometheusOutput {
+    registry: Arc<Mutex<Registry>>,
+    counters: Vec<String>,
+    stop: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+/// The latest value of every series, grouped by metric family.
+struct Registry {
+    families: BTreeMap<String, Family>,
+    staleness: Duration,
+}
+
+struct Family {
+    help: String,
+    unit: Option<&'static str>,
+    is_counter: bool,
+    /// Latest sample of each series, identified by its labels (sorted by name).
+    series: BTreeMap<Vec<(String, String)>, Sample>,
+}
+
+struct Sample {
+    value: WrappedMeasurementValue,
+    updated_at: Instant,
+}
+
+/// Text format of the response.
+#[derive(Clone, Copy)]
+enum Format {
+    Prometheus,
+    OpenMetrics,
+}
+
+/// How often the server checks whether it must stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+impl PrometheusOutput {
+    /// Binds the endpoint and starts the HTTP server in a background thread.
+    pub fn start(config: &PrometheusConfig) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind(config.address)
+            .with_context(|| format!("failed to listen on {} for the Prometheus endpoint", config.address))?;
+        // Use a non-blocking listener to be able to stop the server.
+        listener.set_nonblocking(true)?;
+
+        let registry = Arc::new(Mutex::new(Registry {
+            families: BTreeMap::new(),
+            staleness: config.staleness,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = {
+            let registry = registry.clone();
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name(String::from("example-prometheus"))
+                .spawn(move || serve(listener, registry, stop))?
+        };
+        log::info!("Prometheus endpoint available at http://{}/metrics", config.address);
+        Ok(Self {
+            registry,
+            counters: config.counters.clone(),
+            stop,
+            server: Some(server),
+        })
+    }
+}
+
+impl Output for PrometheusOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        let now = Instant::now();
+        let mut registry = self.registry.lock().unwrap();
+        for m in measurements.iter() {
+            let metric = ctx
+                .metrics
+                .by_id(&m.metric)
+                .with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?;
+            let is_counter = self.counters.contains(&metric.name);
+            let unit = prometheus_unit(metric);
+            let family = registry
+                .families
+                .entry(family_name(&metric.name, unit, is_counter))
+                .or_insert_with(|| Family {
+                    help: metric.description.clone(),
+                    unit,
+                    is_counter,
+                    series: BTreeMap::new(),
+                });
+            family.series.insert(
+                labels(m),
+                Sample {
+                    value: m.value.clone(),
+                    updated_at: now,
+                },
+            );
+        }
+        registry.remove_stale(now);
+        Ok(())
+    }
+}
+
+impl Drop for PrometheusOutput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            if server.join().is_err() {
+                log::error!("the Prometheus endpoint has panicked");
+            }
+        }
+    }
+}
+
+impl Registry {
+    fn remove_stale(&mut self, now: Instant) {
+        let staleness = self.staleness;
+        for family in self.families.values_mut() {
+            family
+                .series
+                .retain(|_, sample| now.duration_since(sample.updated_at) < staleness);
+        }
+        self.families.retain(|_, family| !family.series.is_empty());
+    }
+
+    /// Renders all the series in the given text format.
+    fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        for (name, family) in &self.families {
+            let kind = if family.is_counter { "counter" } else { "gauge" };
+            // In the Prometheus format, the name of the samples of counters is the name of the family.
+            // In OpenMetrics, it is suffixed by `_total`.
+            let (family_name, sample_name) = match format {
+                Format::Prometheus if family.is_counter => (format!("{name}_total"), format!("{name}_total")),
+                Format::OpenMetrics if family.is_counter => (name.clone(), format!("{name}_total")),
+                _ => (name.clone(), name.clone()),
+            };
+            writeln!(out, "# HELP {family_name} {}", escape_help(&family.help, format)).unwrap();
+            writeln!(out, "# TYPE {family_name} {kind}").unwrap();
+            if let (Format::OpenMetrics, Some(unit)) = (format, family.unit) {
+                writeln!(out, "# UNIT {family_name} {unit}").unwrap();
+            }
+            for (labels, sample) in &family.series {
+                let labels = labels
+                    .iter()
+                    .map(|(k, v)| format!("{k}=\"{}\"", escape_label_value(v)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let value = match sample.value {
+                    WrappedMeasurementValue::F64(x) => format_float(x),
+                    WrappedMeasurementValue::U64(x) => x.to_string(),
+                };
+                writeln!(out, "{sample_name}{{{labels}}} {value}").unwrap();
+            }
+        }
+        if let Format::OpenMetrics = format {
+            out.push_str("# EOF\n");
+        }
+        out
+    }
+}
+
+/// Accepts connections until `stop` is set.
+fn serve(listener: TcpListener, registry: Arc<Mutex<Registry>>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = handle_request(stream, &registry) {
+                    log::warn!("failed to answer the Prometheus request of {peer}: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(STOP_CHECK_INTERVAL),
+            Err(e) => log::error!("failed to accept a connection on the Prometheus endpoint: {e}"),
+        }
+    }
+}
+
+/// Reads a HTTP request and answers it.
+fn handle_request(stream: TcpStream, registry: &Mutex<Registry>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream);
+
+    // Parse the request line and the headers, stop at the empty line.
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut accept = String::new();
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("accept") {
+                accept = value.trim().to_owned();
+            }
+        }
+    }
+
+    let mut stream = reader.into_inner();
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    if method != Some("GET") || path.is_none_or(|p| p.split('?').next() != Some("/metrics")) {
+        let body = "not found, use GET /metrics\n";
+        return write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+    }
+
+    // Content negotiation: OpenMetrics if the client asks for it.
+    let (format, content_type) = if accept.contains("application/openmetrics-text") {
+        (
+            Format::OpenMetrics,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )
+    } else {
+        (Format::Prometheus, "text/plain; version=0.0.4; charset=utf-8")
+    };
+    let body = {
+        let mut registry = registry.lock().unwrap();
+        registry.remove_stale(Instant::now());
+        registry.render(format)
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Returns the name of the metric family: the metric name, made valid for Prometheus, with the unit as a suffix.
+fn family_name(metric_name: &str, unit: Option<&str>, is_counter: bool) -> String {
+    let mut name = sanitize_name(metric_name);
+    if is_counter {
+        // The `_total` suffix is added when rendering the counters.
+        if let Some(stripped) = name.strip_suffix("_total") {
+            name = stripped.to_owned();
+        }
+    }
+    if let Some(unit) = unit {
+        // OpenMetrics requires the name of the family to end with its unit.
+        if !name.ends_with(&format!("_{unit}")) {
+            name = format!("{name}_{unit}");
+        }
+    }
+    name
+}
+
+/// Returns the Prometheus base unit of the metric, if any.
+///
+/// Prometheus only uses base units (no prefix), hence the metrics with a prefix have no unit.
+fn prometheus_unit(metric: &Metric) -> Option<&'static str> {
+    if !matches!(metric.unit.prefix, UnitPrefix::Plain) {
+        return None;
+    }
+    match metric.unit.base_unit {
+        Unit::Second => Some("seconds"),
+        Unit::Joule => Some("joules"),
+        Unit::Watt => Some("watts"),
+        Unit::Volt => Some("volts"),
+        Unit::Ampere => Some("amperes"),
+        Unit::Hertz => Some("hertz"),
+        Unit::Byte => Some("bytes"),
+        Unit::DegreeCelsius => Some("celsius"),
+        _ => None,
+    }
+}
+
+/// Returns the labels of the point: resource, consumer and attributes, sorted by name.
+///
+/// The attributes cannot replace the labels of the resource and consumer, see [`attribute_key`].
+/// If several attributes have the same name once sanitized, only the first one is kept.
+fn labels(m: &MeasurementPoint) -> Vec<(String, String)> {
+    let mut labels = vec![
+        (String::from("resource_kind"), m.resource.kind().to_owned()),
+        (String::from("resource_id"), m.resource.id_display().to_string()),
+        (String::from("consumer_kind"), m.consumer.kind().to_owned()),
+        (String::from("consumer_id"), m.consumer.id_display().to_string()),
+    ];
+    labels.extend(
+        m.attributes()
+            .map(|(key, value)| (attribute_key(&sanitize_name(key)).into_owned(), value.to_string())),
+    );
+    // The sort is stable: for each name, the built-in label or the first attribute comes first.
+    labels.sort_by(|(a, _), (b, _)| a.cmp(b));
+    labels.dedup_by(|(a, _), (b, _)| a == b);
+    labels
+}
+
+/// Replaces the characters that are not allowed in Prometheus names by `_`.
+fn sanitize_name(name: &str) -> String {
+    let mut res: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if res.starts_with(|c: char| c.is_ascii_digit()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+/// Escapes the text of a `# HELP` line.
+///
+/// Both formats escape the backslashes and the line breaks, OpenMetrics also escapes the double quotes.
+fn escape_help(help: &str, format: Format) -> String {
+    match format {
+        Format::Prometheus => help.replace('\\', "\\\\").replace('\n', "\\n"),
+        Format::OpenMetrics => escape_label_value(help),
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        String::from("NaN")
+    } else if x.is_infinite() {
+        String::from(if x > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        x.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::Timestamp;
+    use alumet::metrics::RawMetricId;
+    use alumet::resources::{Resource, ResourceConsumer};
+
+    use super::*;
+
+    fn point() -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(1)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 0 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(1.5),
+        )
+    }
+
+    fn pairs(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+        labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn attributes_do_not_replace_the_builtin_labels() {
+        let m = point()
+            .with_attr("resource_kind", "battery")
+            .with_attr("consumer-id", "1");
+        assert_eq!(
+            labels(&m),
+            pairs(&[
+                ("attr_consumer_id", "1"),
+                ("attr_resource_kind", "battery"),
+                ("consumer_id", ""),
+                ("consumer_kind", "local_machine"),
+                ("resource_id", "0"),
+                ("resource_kind", "cpu_package"),
+            ])
+        );
+    }
+
+    #[test]
+    fn labels_are_unique() {
+        let m = point().with_attr("domain.name", "a").with_attr("domain-name", "b");
+        let labels = labels(&m);
+        assert_eq!(labels.iter().filter(|(k, _)| k == "domain_name").count(), 1);
+    }
+
+    #[test]
+    fn escape_the_help() {
+        let help = "the \"energy\"\nof the domain \\ package";
+        assert_eq!(
+            escape_help(help, Format::Prometheus),
+            "the \"energy\"\\nof the domain \\\\ package"
+        );
+        assert_eq!(
+            escape_help(help, Format::OpenMetrics),
+            "the \\\"energy\\\"\\nof the domain \\\\ package"
+        );
+    }
+
+    #[test]
+    fn render_counters_and_gauges() {
+        let sample = |value| Sample {
+            value,
+            updated_at: Instant::now(),
+        };
+        let mut families = BTreeMap::new();
+        families.insert(
+            String::from("example_calls"),
+            Family {
+                help: String::from("number of calls"),
+                unit: None,
+                is_counter: true,
+                series: BTreeMap::from([(pairs(&[("k", "v")]), sample(WrappedMeasurementValue::U64(3)))]),
+            },
+        );
+        families.insert(
+            String::from("example_power_watts"),
+            Family {
+                help: String::from("power"),
+                unit: Some("watts"),
+                is_counter: false,
+                series: BTreeMap::from([(Vec::new(), sample(WrappedMeasurementValue::F64(f64::INFINITY)))]),
+            },
+        );
+        let registry = Registry {
+            families,
+            staleness: Duration::from_secs(60),
+        };
+        assert_eq!(
+            registry.render(Format::Prometheus),
+            "# HELP example_calls_total number of calls\n\
+             # TYPE example_calls_total counter\n\
+             example_calls_total{k=\"v\"} 3\n\
+             # HELP example_power_watts power\n\
+             # TYPE example_power_watts gauge\n\
+             example_power_watts{} +Inf\n"
+        );
+        assert_eq!(
+            registry.render(Format::OpenMetrics),
+            "# HELP example_calls number of calls\n\
+             # TYPE example_calls counter\n\
+             example_calls_total{k=\"v\"} 3\n\
+             # HELP example_power_watts power\n\
+             # TYPE example_power_watts gauge\n\
+             # UNIT example_power_watts watts\n\
+             example_power_watts{} +Inf\n\
+             # EOF\n"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Size- and time-based rotation of output files.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+/// Configuration of the rotation.
+///
+/// The file is rotated when it exceeds `max_size` bytes or when it is older than `max_age`.
+/// If both are `None`, the file is never rotated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationConfig {
+    /// Maximum size of the file, in bytes.
+    pub max_size: Option<u64>,
+    /// Maximum age of the file.
+    #[serde(default, with = "humantime_serde")]
+    pub max_age: Option<Duration>,
+    /// How many rotated files to keep. The oldest ones are deleted.
+    pub keep: usize,
+    /// If true, rotated files are compressed with gzip.
+    pub compress: bool,
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_age: None,
+            keep: 5,
+            compress: false,
+        }
+    }
+}
+
+/// Gives the current time.
+///
+/// Use [`SystemClock`] in the plugin, and a fake clock in tests to control the age of the files.
+pub trait Clock: Send {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real clock of the system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A buffered file that rotates according to a [`RotationConfig`].
+///
+/// Data is written by records (e.g. lines) with [`RotatingFile::write_record`].
+/// The rotation only happens between two records: a record is never split across two files.
+///
+/// Rotated files are named after the current file, with a numeric suffix: `output.txt.1` is the most recent one,
+/// `output.txt.2` is older, and so on (followed by `.gz` if they are compressed).
+pub struct RotatingFile<C: Clock = SystemClock> {
+    path: PathBuf,
+    rotation: RotationConfig,
+    writer: BufWriter<File>,
+    /// Size of the current file, including the data that is still in the buffer.
+    size: u64,
+    /// When the current file has been opened.
+    opened_at: SystemTime,
+    /// Data to write at the beginning of each new file, e.g. the header of a CSV file.
+    header: Vec<u8>,
+    clock: C,
+}
+
+impl RotatingFile<SystemClock> {
+    /// Opens the file at `path`, in append or truncate mode.
+    pub fn open(path: &Path, append: bool, rotation: RotationConfig) -> io::Result<Self> {
+        Self::with_clock(path, append, rotation, SystemClock)
+    }
+}
+
+impl<C: Clock> RotatingFile<C> {
+    /// Opens the file at `path`, in append or truncate mode, and uses the given clock to determine its age.
+    pub fn with_clock(path: &Path, append: bool, rotation: RotationConfig, clock: C) -> io::Result<Self> {
+        let file = open_file(path, append)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        // A file that already contains records keeps its age when the agent restarts, otherwise an agent that
+        // restarts more often than `max_age` would never rotate it. Some filesystems do not provide the creation
+        // time of the files: in that case, the age starts now.
+        let opened_at = if size > 0 {
+            metadata.created().unwrap_or_else(|_| clock.now())
+        } else {
+            clock.now()
+        };
+        Ok(Self {
+            path: path.to_owned(),
+            rotation,
+            writer: BufWriter::new(file),
+            size,
+            opened_at,
+            header: Vec::new(),
+            clock,
+        })
+    }
+
+    /// Sets the data to write at the beginning of each new file.
+    ///
+    /// It is written before the first record of the file. Files opened in append mode that are not empty
+    /// are considered to already contain the header.
+    pub fn set_header(&mut self, header: impl Into<Vec<u8>>) {
+        self.header = header.into();
+    }
+
+    /// Writes a complete record to the file, rotating it beforehand if needed.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        if self.must_rotate(record.len() as u64) {
+            self.rotate()?;
+        }
+        if self.size == 0 && !self.header.is_empty() {
+            self.writer.write_all(&self.header)?;
+            self.size += self.header.len() as u64;
+        }
+        self.writer.write_all(record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Flushes the buffered data to the file, and synchronizes the file to the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+
+    /// Returns `true` if the file must be rotated before writing `record_len` more bytes.
+    fn must_rotate(&self, record_len: u64) -> bool {
+        // Never rotate a file that contains no record: a single record can be bigger than the maximum size.
+        if self.size <= self.header.len() as u64 {
+            return false;
+        }
+        let too_big = self.rotation.max_size.is_some_and(|max| self.size + record_len > max);
+        let too_old = self.rotation.max_age.is_some_and(|max| {
+            // If the clock goes backwards, consider that the file is new.
+            let age = self.clock.now().duration_since(self.opened_at).unwrap_or_default();
+            age >= max
+        });
+        too_big || too_old
+    }
+
+    /// Moves the current file to the rotated files, and starts a new, empty, file.
+    fn rotate(&mut self) -> io::Result<()> {
+        // Make sure that everything we wrote is in the file before moving it.
+        self.sync()?;
+
+        let keep = self.rotation.keep;
+        if keep > 0 {
+            // Shift the rotated files: .1 becomes .2, etc. The oldest one is overwritten.
+            for i in (1..keep).rev() {
+                let from = self.rotated_path(i);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(i + 1))?;
+                }
+            }
+            let first = self.rotated_path(1);
+            if self.rotation.compress {
+                compress_file(&self.path, &first)?;
+            } else {
+                std::fs::rename(&self.path, &first)?;
+            }
+        }
+
+        // Truncate (or create) the file.
+        self.writer = BufWriter::new(open_file(&self.path, false)?);
+        self.size = 0;
+        self.opened_at = self.clock.now();
+        Ok(())
+    }
+
+    /// Returns the path of the n-th rotated file.
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{n}"));
+        if self.rotation.compress {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+}
+
+fn open_file(path: &Path, append: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    if append {
+        options.append(true);
+    } else {
+        options.write(true).truncate(true);
+    }
+    options.create(true).open(path)
+}
+
+/// Compresses the file at `source` to `target` with gzip, then removes `source`.
+fn compress_file(source: &Path, target: &Path) -> io::Result<()> {
+    // Write to a temporary file first, so that `target` is never left half-written.
+    let mut tmp_name = OsString::from(target.as_os_str());
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+
+    let mut input = File::open(source)?;
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&tmp)?), Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    std::fs::rename(&tmp, target)?;
+    std::fs::remove_file(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    /// A clock that only moves when the test advances it.
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<SystemTime>>);
+
+    impl FakeClock {
+        fn new() -> Self {
+            Self(Arc::new(Mutex::new(SystemTime::now())))
+        }
+
+        fn advance(&self, d: Duration) {
+            *self.0.lock().unwrap() += d;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> SystemTime {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn rotation(max_size: Option<u64>, max_age: Option<Duration>, keep: usize, compress: bool) -> RotationConfig {
+        RotationConfig {
+            max_size,
+            max_age,
+            keep,
+            compress,
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    #[test]
+    fn rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(10), None, 5, false)).unwrap();
+        file.write_record(b"0123456\n").unwrap();
+        file.write_record(b"abcdef\n").unwrap(); // 8 + 7 > 10: rotate before this record
+        file.sync().unwrap();
+        assert_eq!(read(&with_suffix(&path, ".1")), "0123456\n");
+        assert_eq!(read(&path), "abcdef\n");
+    }
+
+    #[test]
+    fn never_rotate_an_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(4), None, 5, false)).unwrap();
+        file.write_record(b"a record bigger than the limit\n").unwrap();
+        file.sync().unwrap();
+        assert_eq!(read(&path), "a record bigger than the limit\n");
+        assert!(!with_suffix(&path, ".1").exists());
+    }
+
+    #[test]
+    fn rotate_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let clock = FakeClock::new();
+        let config = rotation(None, Some(Duration::from_secs(60)), 5, false);
+        let mut file = RotatingFile::with_clock(&path, false, config, clock.clone()).unwrap();
+        file.write_record(b"first\n").unwrap();
+        clock.advance(Duration::from_secs(59));
+        file.write_record(b"second\n").unwrap();
+        clock.advance(Duration::from_secs(1));
+        file.write_record(b"third\n").unwrap();
+        file.sync().unwrap();
+        assert_eq!(read(&with_suffix(&path, ".1")), "first\nsecond\n");
+        assert_eq!(read(&path), "third\n");
+    }
+
+    #[test]
+    fn age_survives_a_restart_in_append_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        std::fs::write(&path, "before the restart\n").unwrap();
+        let Ok(created) = std::fs::metadata(&path).unwrap().created() else {
+            return; // the filesystem does not provide the creation time
+        };
+
+        // The agent restarts one hour after the creation of the file.
+        let clock = FakeClock(Arc::new(Mutex::new(created + Duration::from_secs(3600))));
+        let config = rotation(None, Some(Duration::from_secs(60)), 5, false);
+        let mut file = RotatingFile::with_clock(&path, true, config, clock).unwrap();
+        file.write_record(b"after the restart\n").unwrap();
+        file.sync().unwrap();
+        assert_eq!(read(&with_suffix(&path, ".1")), "before the restart\n");
+        assert_eq!(read(&path), "after the restart\n");
+    }
+
+    #[test]
+    fn keep_the_most_recent_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(1), None, 2, false)).unwrap();
+        for record in ["1\n", "2\n", "3\n", "4\n"] {
+            file.write_record(record.as_bytes()).unwrap();
+        }
+        file.sync().unwrap();
+        assert_eq!(read(&path), "4\n");
+        assert_eq!(read(&with_suffix(&path, ".1")), "3\n");
+        assert_eq!(read(&with_suffix(&path, ".2")), "2\n");
+        assert!(!with_suffix(&path, ".3").exists());
+    }
+
+    #[test]
+    fn keep_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(1), None, 0, false)).unwrap();
+        file.write_record(b"1\n").unwrap();
+        file.write_record(b"2\n").unwrap();
+        file.sync().unwrap();
+        assert_eq!(read(&path), "2\n");
+        assert!(!with_suffix(&path, ".1").exists());
+    }
+
+    #[test]
+    fn compress_rotated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(1), None, 5, true)).unwrap();
+        file.write_record(b"compressed\n").unwrap();
+        file.write_record(b"current\n").unwrap();
+        file.sync().unwrap();
+
+        let mut decompressed = String::new();
+        let gz = File::open(with_suffix(&path, ".1.gz")).unwrap();
+        GzDecoder::new(gz).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, "compressed\n");
+        assert_eq!(read(&path), "current\n");
+        assert!(!with_suffix(&path, ".1").exists());
+        assert!(!with_suffix(&path, ".1.gz.tmp").exists());
+    }
+
+    #[test]
+    fn header_after_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let mut file = RotatingFile::open(&path, false, rotation(Some(12), None, 5, false)).unwrap();
+        file.set_header("h1,h2\n");
+        file.write_record(b"1,2\n").unwrap();
+        file.write_record(b"3,4\n").unwrap(); // 6 + 4 + 4 > 12
+        file.sync().unwrap();
+        assert_eq!(read(&with_suffix(&path, ".1")), "h1,h2\n1,2\n");
+        assert_eq!(read(&path), "h1,h2\n3,4\n");
+    }
+
+    #[test]
+    fn no_header_when_appending_to_a_non_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        std::fs::write(&path, "h1,h2\n1,2\n").unwrap();
+        let mut file = RotatingFile::open(&path, true, RotationConfig::default()).unwrap();
+        file.set_header("h1,h2\n");
+        file.write_record(b"3,4\n").unwrap();
+        file.sync().unwrap();
+        assert_eq!(read(&path), "h1,h2\n1,2\n3,4\n");
+    }
+}
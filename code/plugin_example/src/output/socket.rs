@@ -0,0 +1,83 @@
+//! Network sockets, as an alternative to output files.
+
+use std::io::{self, BufWriter, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a socket to send the measurements to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocketConfig {
+    pub protocol: SocketProtocol,
+    /// Address of the server, for instance `localhost:8089`.
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SocketProtocol {
+    Tcp,
+    Udp,
+}
+
+impl SocketConfig {
+    /// Resolves the address of the server.
+    pub fn resolve(&self) -> anyhow::Result<SocketAddr> {
+        self.address
+            .to_socket_addrs()
+            .with_context(|| format!("invalid socket address: {}", self.address))?
+            .next()
+            .with_context(|| format!("socket address {} does not resolve to anything", self.address))
+    }
+
+    /// Connects to the server.
+    pub fn connect(&self) -> anyhow::Result<SocketWriter> {
+        let addr = self.resolve()?;
+        let writer = match self.protocol {
+            SocketProtocol::Tcp => {
+                let stream = TcpStream::connect(addr).with_context(|| format!("failed to connect to {addr}"))?;
+                SocketWriter::Tcp(BufWriter::new(stream))
+            }
+            SocketProtocol::Udp => {
+                // Bind to an ephemeral port of the same family as the server.
+                let local: SocketAddr = if addr.is_ipv4() {
+                    "0.0.0.0:0".parse().unwrap()
+                } else {
+                    "[::]:0".parse().unwrap()
+                };
+                let socket = UdpSocket::bind(local).context("failed to bind the UDP socket")?;
+                socket
+                    .connect(addr)
+                    .with_context(|| format!("failed to connect the UDP socket to {addr}"))?;
+                SocketWriter::Udp(socket)
+            }
+        };
+        Ok(writer)
+    }
+}
+
+/// Sends records (e.g. lines) to a socket.
+pub enum SocketWriter {
+    Tcp(BufWriter<TcpStream>),
+    /// Each record is sent in its own datagram.
+    Udp(UdpSocket),
+}
+
+impl SocketWriter {
+    /// Sends a complete record.
+    pub fn write_record(&mut self, record: &[u8]) -> io::Result<()> {
+        match self {
+            SocketWriter::Tcp(stream) => stream.write_all(record),
+            SocketWriter::Udp(socket) => socket.send(record).map(|_| ()),
+        }
+    }
+
+    /// Sends the buffered data, if any.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SocketWriter::Tcp(stream) => stream.flush(),
+            SocketWriter::Udp(_) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Output that stores the measurements in a SQLite database.
+//!
+//! The schema is normalized: the `metrics` table contains the definition of the metrics,
+//! the `series` table contains the combinations of metric, resource, consumer and attributes,
+//! and the `points` table contains the measured values.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use alumet::measurement::{MeasurementBuffer, MeasurementPoint, WrappedMeasurementType, WrappedMeasurementValue};
+use alumet::metrics::{Metric, RawMetricId};
+use alumet::pipeline::elements::error::WriteError;
+use alumet::pipeline::elements::output::OutputContext;
+use alumet::pipeline::Output;
+use anyhow::Context;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+
+use super::unix_nanos;
+
+/// Configuration of the SQLite output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SqliteConfig {
+    /// If true, the measurements are stored in the database.
+    pub enabled: bool,
+    /// Path of the database file. It is created if it does not exist.
+    pub path: PathBuf,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("alumet-tutorial.sqlite"),
+        }
+    }
+}
+
+/// The migrations of the schema, in order.
+///
+/// The version of the schema, stored in `PRAGMA user_version`, is the number of migrations that have been applied.
+/// To change the schema, add a new migration at the end: never modify the existing ones.
+const MIGRATIONS: &[&str] = &[
+    // Version 1: initial schema.
+    // The points have one column per type of value, so that SQLite never converts a float to an integer.
+    "CREATE TABLE metrics (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        unit TEXT NOT NULL,
+        value_type TEXT NOT NULL,
+        description TEXT NOT NULL
+    );
+    CREATE TABLE series (
+        id INTEGER PRIMARY KEY,
+        metric_id INTEGER NOT NULL REFERENCES metrics(id),
+        resource_kind TEXT NOT NULL,
+        resource_id TEXT NOT NULL,
+        consumer_kind TEXT NOT NULL,
+        consumer_id TEXT NOT NULL,
+        attributes TEXT NOT NULL,
+        UNIQUE (metric_id, resource_kind, resource_id, consumer_kind, consumer_id, attributes)
+    );
+    CREATE TABLE points (
+        series_id INTEGER NOT NULL REFERENCES series(id),
+        timestamp INTEGER NOT NULL,
+        value_u64 INTEGER,
+        value_f64 REAL
+    );
+    CREATE INDEX points_by_series ON points (series_id, timestamp);",
+];
+
+/// Writes the measurements to a SQLite database, in one transaction per call to `write`.
+pub struct SqliteOutput {
+    conn: Connection,
+    /// Row ids of the metrics that have already been inserted.
+    metric_ids: HashMap<RawMetricId, i64>,
+    /// Row ids of the series that have already been inserted.
+    series_ids: HashMap<SeriesColumns, i64>,
+}
+
+/// The columns of the `series` table (except its id).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SeriesColumns {
+    metric_id: i64,
+    resource_kind: String,
+    resource_id: String,
+    consumer_kind: String,
+    consumer_id: String,
+    /// The attributes, as a JSON object with sorted keys.
+    attributes: String,
+}
+
+/// The last value of a series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LastValue {
+    pub resource_kind: String,
+    pub resource_id: String,
+    pub consumer_kind: String,
+    pub consumer_id: String,
+    pub attributes: String,
+    /// Nanoseconds since the UNIX epoch.
+    pub timestamp: i64,
+    pub value: WrappedMeasurementValue,
+}
+
+impl SqliteOutput {
+    /// Opens (or creates) the database and migrates its schema to the latest version.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut conn =
+            Connection::open(path).with_context(|| format!("failed to open SQLite database {}", path.display()))?;
+        migrate(&mut conn).with_context(|| format!("failed to migrate SQLite database {}", path.display()))?;
+        Ok(Self {
+            conn,
+            metric_ids: HashMap::new(),
+            series_ids: HashMap::new(),
+        })
+    }
+
+    /// Returns the connection to the database, for instance to query it with [`last_values`].
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Inserts the measurements in one transaction. `metrics` returns the definition of a metric.
+    fn insert_all<'a>(
+        &mut self,
+        measurements: &MeasurementBuffer,
+        metrics: impl Fn(&RawMetricId) -> Option<&'a Metric>,
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.transaction()?;
+        for m in measurements.iter() {
+            let metric_id = match self.metric_ids.get(&m.metric) {
+                Some(id) => *id,
+                None => {
+                    let metric =
+                        metrics(&m.metric).with_context(|| format!("unregistered metric id: {}", m.metric.as_u64()))?;
+                    let id = insert_metric(&tx, metric)?;
+                    self.metric_ids.insert(m.metric, id);
+                    id
+                }
+            };
+            let series = SeriesColumns::of(m, metric_id)?;
+            let series_id = match self.series_ids.get(&series) {
+                Some(id) => *id,
+                None => {
+                    let id = insert_series(&tx, &series)?;
+                    self.series_ids.insert(series, id);
+                    id
+                }
+            };
+            tx.execute(
+                "INSERT INTO points (series_id, timestamp, value_u64, value_f64) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    series_id,
+                    timestamp_column(m),
+                    u64_column(&m.value),
+                    f64_column(&m.value)
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl Output for SqliteOutput {
+    fn write(&mut self, measurements: &MeasurementBuffer, ctx: &OutputContext) -> Result<(), WriteError> {
+        if let Err(e) = self.insert_all(measurements, |id| ctx.metrics.by_id(id)) {
+            // The transaction has been rolled back: the cached ids may refer to rows that do not exist.
+            self.metric_ids.clear();
+            self.series_ids.clear();
+            return Err(e
+                .context("failed to insert the measurements in the SQLite database")
+                .into());
+        }
+        Ok(())
+    }
+}
+
+impl SeriesColumns {
+    fn of(m: &MeasurementPoint, metric_id: i64) -> anyhow::Result<Self> {
+        // serde_json::Map is sorted by key, which gives a stable representation of the attributes.
+        let attributes: serde_json::Map<String, serde_json::Value> = m
+            .attributes()
+            .map(|(key, value)| (key.to_string(), serde_json::Value::from(value.to_string())))
+            .collect();
+        Ok(Self {
+            metric_id,
+            resource_kind: m.resource.kind().to_owned(),
+            resource_id: m.resource.id_display().to_string(),
+            consumer_kind: m.consumer.kind().to_owned(),
+            consumer_id: m.consumer.id_display().to_string(),
+            attributes: serde_json::to_string(&attributes)?,
+        })
+    }
+}
+
+/// Applies the migrations that have not been applied yet.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    let version: usize = tx.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        log::debug!("migrating the SQLite database to version {}", i + 1);
+        tx.execute_batch(migration)?;
+    }
+    // PRAGMA does not support parameters.
+    tx.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))?;
+    tx.commit()
+}
+
+/// Inserts (or updates) the definition of a metric, and returns its row id.
+fn insert_metric(tx: &Transaction, metric: &Metric) -> rusqlite::Result<i64> {
+    let value_type = match metric.value_type {
+        WrappedMeasurementType::F64 => "f64",
+        WrappedMeasurementType::U64 => "u64",
+    };
+    tx.query_row(
+        "INSERT INTO metrics (name, unit, value_type, description) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (name) DO UPDATE SET unit = excluded.unit, value_type = excluded.value_type, description = excluded.description
+         RETURNING id",
+        params![metric.name, metric.unit.unique_name().to_string(), value_type, metric.description],
+        |row| row.get(0),
+    )
+}
+
+/// Inserts a series if it does not exist, and returns its row id.
+fn insert_series(tx: &Transaction, s: &SeriesColumns) -> rusqlite::Result<i64> {
+    let existing = tx
+        .query_row(
+            "SELECT id FROM series WHERE metric_id = ?1 AND resource_kind = ?2 AND resource_id = ?3
+             AND consumer_kind = ?4 AND consumer_id = ?5 AND attributes = ?6",
+            params![
+                s.metric_id,
+                s.resource_kind,
+                s.resource_id,
+                s.consumer_kind,
+                s.consumer_id,
+                s.attributes
+            ],
+            |row| row.get(0),
+        )
+        .optional()?;
+    match existing {
+        Some(id) => Ok(id),
+        None => {
+            tx.execute(
+                "INSERT INTO series (metric_id, resource_kind, resource_id, consumer_kind, consumer_id, attributes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    s.metric_id,
+                    s.resource_kind,
+                    s.resource_id,
+                    s.consumer_kind,
+                    s.consumer_id,
+                    s.attributes
+                ],
+            )?;
+            Ok(tx.last_insert_rowid())
+        }
+    }
+}
+
+/// Returns the timestamp of the point in nanoseconds since the UNIX epoch.
+///
+/// SQLite integers are 64-bit, which is enough until the year 2262.
+fn timestamp_column(m: &MeasurementPoint) -> i64 {
+    i64::try_from(unix_nanos(m.timestamp)).unwrap_or(i64::MAX)
+}
+
+/// Returns the value of the `value_u64` column: the value if it is an integer, otherwise NULL.
+///
+/// SQLite integers are signed: the unsigned value is stored with the same bits, without any loss of precision.
+/// The values above `i64::MAX` look negative in SQL, [`last_values`] converts them back.
+fn u64_column(value: &WrappedMeasurementValue) -> Value {
+    match *value {
+        WrappedMeasurementValue::F64(_) => Value::Null,
+        WrappedMeasurementValue::U64(x) => Value::Integer(x as i64),
+    }
+}
+
+/// Returns the value of the `value_f64` column: the value if it is a float, otherwise NULL.
+fn f64_column(value: &WrappedMeasurementValue) -> Value {
+    match *value {
+        WrappedMeasurementValue::F64(x) => Value::Real(x),
+        WrappedMeasurementValue::U64(_) => Value::Null,
+    }
+}
+
+/// Returns the last value of every series of the metric `metric_name`.
+pub fn last_values(conn: &Connection, metric_name: &str) -> rusqlite::Result<Vec<LastValue>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.resource_kind, s.resource_id, s.consumer_kind, s.consumer_id, s.attributes, p.timestamp,
+                m.value_type, p.value_u64, p.value_f64
+         FROM series s
+         JOIN metrics m ON m.id = s.metric_id
+         JOIN points p ON p.series_id = s.id
+         WHERE m.name = ?1
+           AND p.timestamp = (SELECT MAX(timestamp) FROM points WHERE series_id = s.id)
+         ORDER BY s.id",
+    )?;
+    let rows = stmt.query_map([metric_name], |row| {
+        // The type of the value is given by the metric, not by the type of the SQLite value.
+        let value_type: String = row.get(6)?;
+        let value = match (value_type.as_str(), row.get::<_, Value>(7)?, row.get::<_, Value>(8)?) {
+            ("u64", Value::Integer(x), _) => WrappedMeasurementValue::U64(x as u64),
+            ("f64", _, Value::Real(x)) => WrappedMeasurementValue::F64(x),
+            // SQLite stores NaN as NULL.
+            ("f64", _, Value::Null) => WrappedMeasurementValue::F64(f64::NAN),
+            (_, u, f) => {
+                let (i, column, value) = if value_type == "f64" {
+                    (8, "value_f64", f)
+                } else {
+                    (7, "value_u64", u)
+                };
+                return Err(rusqlite::Error::InvalidColumnType(
+                    i,
+                    String::from(column),
+                    value.data_type(),
+                ));
+            }
+        };
+        Ok(LastValue {
+            resource_kind: row.get(0)?,
+            resource_id: row.get(1)?,
+            consumer_kind: row.get(2)?,
+            consumer_id: row.get(3)?,
+            attributes: row.get(4)?,
+            timestamp: row.get(5)?,
+            value,
+        })
+    })?;
+    rows.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use alumet::measurement::Timestamp;
+    use alumet::resources::{Resource, ResourceConsumer};
+    use alumet::units::Unit;
+
+    use super::*;
+
+    fn metric(name: &str, value_type: WrappedMeasurementType) -> Metric {
+        Metric {
+            name: name.to_owned(),
+            description: String::new(),
+            value_type,
+            unit: Unit::Unity.into(),
+        }
+    }
+
+    fn point(metric: u64, secs: u64, package: u32, value: WrappedMeasurementValue) -> MeasurementPoint {
+        MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(secs)),
+            RawMetricId::from_u64(metric),
+            Resource::CpuPackage { id: package },
+            ResourceConsumer::LocalMachine,
+            value,
+        )
+    }
+
+    fn values(conn: &Connection, metric_name: &str) -> Vec<(String, i64, WrappedMeasurementValue)> {
+        last_values(conn, metric_name)
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.resource_id, v.timestamp, v.value))
+            .collect()
+    }
+
+    #[test]
+    fn last_value_of_each_series() {
+        let dir = tempfile::tempdir().unwrap();
+        let metrics = HashMap::from([
+            (RawMetricId::from_u64(0), metric("energy", WrappedMeasurementType::F64)),
+            (RawMetricId::from_u64(1), metric("counter", WrappedMeasurementType::U64)),
+        ]);
+        let mut output = SqliteOutput::open(&dir.path().join("db.sqlite")).unwrap();
+
+        let mut buffer = MeasurementBuffer::new();
+        buffer.push(point(0, 1, 0, WrappedMeasurementValue::F64(2.5)));
+        buffer.push(point(0, 2, 0, WrappedMeasurementValue::F64(-3.0)));
+        buffer.push(point(0, 1, 1, WrappedMeasurementValue::F64(f64::NAN)));
+        buffer.push(point(1, 1, 0, WrappedMeasurementValue::U64(7)));
+        output.insert_all(&buffer, |id| metrics.get(id)).unwrap();
+        let mut buffer = MeasurementBuffer::new();
+        buffer.push(point(1, 2, 0, WrappedMeasurementValue::U64(u64::MAX - 1)));
+        buffer.push(point(1, 2, 1, WrappedMeasurementValue::U64(i64::MAX as u64 + 1)));
+        output.insert_all(&buffer, |id| metrics.get(id)).unwrap();
+
+        // The floats with an integer value stay floats.
+        let energy = values(output.connection(), "energy");
+        assert_eq!(
+            energy[0],
+            (String::from("0"), 2_000_000_000, WrappedMeasurementValue::F64(-3.0))
+        );
+        assert_eq!(energy[1].0, "1");
+        assert!(matches!(energy[1].2, WrappedMeasurementValue::F64(x) if x.is_nan()));
+        // The integers above i64::MAX keep all their bits (u64::MAX - 1 is not representable as a f64).
+        assert_eq!(
+            values(output.connection(), "counter"),
+            vec![
+                (
+                    String::from("0"),
+                    2_000_000_000,
+                    WrappedMeasurementValue::U64(u64::MAX - 1)
+                ),
+                (
+                    String::from("1"),
+                    2_000_000_000,
+                    WrappedMeasurementValue::U64(i64::MAX as u64 + 1)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unregistered_metric() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut output = SqliteOutput::open(&dir.path().join("db.sqlite")).unwrap();
+        let mut buffer = MeasurementBuffer::new();
+        buffer.push(point(0, 1, 0, WrappedMeasurementValue::U64(1)));
+        assert!(output.insert_all(&buffer, |_| None).is_err());
+        assert_eq!(values(output.connection(), "counter"), vec![]);
+    }
+}
@@ -0,0 +1,69 @@
+//! Additional sources of the example plugin.
+//!
+//! The sources that read the system state (procfs, sysfs) take a configurable `root` directory,
+//! which is `/` by default. This allows to run them against a fake directory tree.
+
+use std::io;
+use std::path::Path;
+
+use alumet::resources::{Resource, ResourceConsumer};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+pub mod battery;
+pub mod cgroup;
+pub mod diskstats;
+pub mod hwmon;
+pub mod net_dev;
+pub mod proc_stat;
+pub mod process;
+pub mod rapl;
+pub mod replay;
+pub mod signal;
+
+/// A resource or a resource consumer, as written in the config: its kind and its id.
+///
+/// For instance, `{ kind = "cpu_core", id = "0" }` or `{ kind = "local_machine", id = "" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResourceSpec {
+    pub kind: String,
+    #[serde(default)]
+    pub id: String,
+}
+
+impl ResourceSpec {
+    /// The local machine, as a resource or as a consumer.
+    pub fn local_machine() -> Self {
+        Self {
+            kind: String::from("local_machine"),
+            id: String::new(),
+        }
+    }
+
+    pub fn to_resource(&self) -> anyhow::Result<Resource> {
+        Resource::parse(self.kind.clone(), self.id.clone())
+            .with_context(|| format!("invalid resource {}/{}", self.kind, self.id))
+    }
+
+    pub fn to_consumer(&self) -> anyhow::Result<ResourceConsumer> {
+        ResourceConsumer::parse(self.kind.clone(), self.id.clone())
+            .with_context(|| format!("invalid resource consumer {}/{}", self.kind, self.id))
+    }
+}
+
+/// Reads a file of procfs or sysfs, with a helpful error message.
+pub fn read_file(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Reads a file of procfs or sysfs, or returns `None` if it does not exist.
+///
+/// If its directory does not exist either, the file is not optional: the directory has been removed,
+/// for instance a cgroup, and a `NotFound` error is returned.
+pub fn read_optional(path: &Path) -> anyhow::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && path.parent().is_some_and(Path::is_dir) => Ok(None),
+        Err(e) => Err(anyhow::Error::new(e).context(format!("failed to read {}", path.display()))),
+    }
+}
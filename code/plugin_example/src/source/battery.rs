@@ -0,0 +1,387 @@
+//! Source that measures the batteries, from the power supply class of sysfs.
+//!
+//! Each power supply is a directory `/sys/class/power_supply/<name>`, whose file `type` is `Battery` for the batteries.
+//! A battery exposes some of the following files, depending on its driver:
+//! - `energy_now`: remaining energy, in microwatt-hours,
+//! - `power_now`: power drawn from or supplied to the battery, in microwatts,
+//! - `voltage_now`: voltage, in microvolts,
+//! - `current_now`: current, in microamperes,
+//! - `capacity`: remaining capacity, in percent,
+//! - `status`: `Charging`, `Discharging`, `Full`, `Not charging` or `Unknown`.
+//!
+//! When `power_now` is missing, the power is computed from the voltage and the current.
+//!
+//! Each battery is a custom resource of kind `battery`, identified by the name of its directory.
+//! The status is measured by a separate metric, with one point per possible status: the value is 1 for the
+//! current status and 0 for the others. This way, a change of status does not start new series for the other metrics.
+//!
+//! See <https://docs.kernel.org/power/power_supply_class.html>.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{read_file, read_optional};
+
+/// Configuration of the battery source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatteryConfig {
+    /// If true, the batteries are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `sys/class/power_supply`.
+    pub root: PathBuf,
+}
+
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(5),
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+/// The statuses of a battery, as written in its `status` file.
+pub const STATUSES: [&str; 5] = ["Charging", "Discharging", "Full", "Not charging", "Unknown"];
+
+/// The state of a battery, converted to the units of the metrics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryReading {
+    /// Name of the power supply directory, such as `BAT0`.
+    pub name: String,
+    /// One of the [`STATUSES`].
+    pub status: &'static str,
+    /// Remaining energy, in Joules.
+    pub energy: Option<f64>,
+    /// Power, in Watts, always positive: the status tells whether the battery is charging or discharging.
+    /// It is computed from the voltage and the current if the battery does not provide it.
+    pub power: Option<f64>,
+    /// Voltage, in Volts.
+    pub voltage: Option<f64>,
+    /// Current, in Amperes.
+    pub current: Option<f64>,
+    /// Remaining capacity, in percent.
+    pub capacity: Option<f64>,
+}
+
+/// Reads a power supply directory, and returns `None` if it is not a battery.
+pub fn read_battery(dir: &Path) -> anyhow::Result<Option<BatteryReading>> {
+    if read_file(&dir.join("type"))?.trim() != "Battery" {
+        return Ok(None);
+    }
+    let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let status = match read_optional(&dir.join("status"))? {
+        Some(status) => parse_status(status.trim()),
+        None => "Unknown",
+    };
+    let number = |file: &str| -> anyhow::Result<Option<f64>> {
+        read_optional(&dir.join(file))?
+            .map(|content| {
+                let content = content.trim();
+                // Some values, such as the current, are negative when the battery discharges.
+                let value: i64 = content
+                    .parse()
+                    .with_context(|| format!("invalid number in {file}: {content}"))?;
+                Ok(value as f64)
+            })
+            .transpose()
+    };
+    // 1 µWh = 3600 µJ
+    let energy = number("energy_now")?.map(|uwh| uwh * 3.6e-3);
+    let voltage = number("voltage_now")?.map(|uv| uv * 1e-6);
+    let current = number("current_now")?.map(|ua| ua * 1e-6);
+    // The sign of the power depends on the driver: keep its magnitude, the status tells the direction.
+    let power = match number("power_now")? {
+        Some(uw) => Some((uw * 1e-6).abs()),
+        None => voltage.zip(current).map(|(v, i)| (v * i).abs()),
+    };
+    Ok(Some(BatteryReading {
+        name,
+        status,
+        energy,
+        power,
+        voltage,
+        current,
+        capacity: number("capacity")?,
+    }))
+}
+
+/// Returns the status that matches the content of the `status` file, or `Unknown`.
+fn parse_status(status: &str) -> &'static str {
+    STATUSES.into_iter().find(|s| *s == status).unwrap_or_else(|| {
+        log::debug!("unknown battery status {status}");
+        "Unknown"
+    })
+}
+
+/// Reads the batteries of `power_supply_dir`, sorted by name.
+///
+/// A battery that cannot be read is skipped, it does not prevent the other batteries from being measured.
+pub fn read_batteries(power_supply_dir: &Path) -> Vec<BatteryReading> {
+    // There is no power supply directory on some virtual machines.
+    let Ok(entries) = std::fs::read_dir(power_supply_dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort();
+    dirs.into_iter()
+        .filter_map(|dir| match read_battery(&dir) {
+            Ok(reading) => reading,
+            Err(e) => {
+                log::warn!("failed to read power supply {}: {e:#}", dir.display());
+                None
+            }
+        })
+        .collect()
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 6] = [
+    "example_battery_energy",
+    "example_battery_power",
+    "example_battery_voltage",
+    "example_battery_current",
+    "example_battery_capacity",
+    "example_battery_status",
+];
+
+/// Measures the batteries.
+pub struct BatterySource {
+    power_supply_dir: PathBuf,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    energy: TypedMetricId<f64>,
+    power: TypedMetricId<f64>,
+    voltage: TypedMetricId<f64>,
+    current: TypedMetricId<f64>,
+    capacity: TypedMetricId<f64>,
+    status: TypedMetricId<u64>,
+}
+
+impl BatterySource {
+    pub fn new(config: &BatteryConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let percent = Unit::Custom {
+            unique_name: String::from("percent"),
+            display_name: String::from("%"),
+        };
+        let metrics = Metrics {
+            energy: alumet.create_metric::<f64>(
+                "example_battery_energy",
+                Unit::Joule,
+                "energy remaining in the battery",
+            )?,
+            power: alumet.create_metric::<f64>(
+                "example_battery_power",
+                Unit::Watt,
+                "power drawn from or supplied to the battery",
+            )?,
+            voltage: alumet.create_metric::<f64>("example_battery_voltage", Unit::Volt, "voltage of the battery")?,
+            current: alumet.create_metric::<f64>("example_battery_current", Unit::Ampere, "current of the battery")?,
+            capacity: alumet.create_metric::<f64>(
+                "example_battery_capacity",
+                percent,
+                "capacity remaining in the battery",
+            )?,
+            status: alumet.create_metric::<u64>(
+                "example_battery_status",
+                Unit::Unity,
+                "1 if the battery is in this status, 0 otherwise",
+            )?,
+        };
+        Ok(Self {
+            power_supply_dir: config.root.join("sys/class/power_supply"),
+            metrics,
+        })
+    }
+
+    fn push(&self, acc: &mut MeasurementAccumulator, timestamp: Timestamp, b: BatteryReading) {
+        let resource = Resource::Custom {
+            kind: "battery".into(),
+            id: b.name.into(),
+        };
+        let point = |metric, value| {
+            MeasurementPoint::new(
+                timestamp,
+                metric,
+                resource.clone(),
+                ResourceConsumer::LocalMachine,
+                value,
+            )
+        };
+        let values = [
+            (self.metrics.energy, b.energy),
+            (self.metrics.power, b.power),
+            (self.metrics.voltage, b.voltage),
+            (self.metrics.current, b.current),
+            (self.metrics.capacity, b.capacity),
+        ];
+        for (metric, value) in values {
+            if let Some(value) = value {
+                acc.push(point(metric, value));
+            }
+        }
+        for status in STATUSES {
+            let value = u64::from(status == b.status);
+            acc.push(
+                MeasurementPoint::new(
+                    timestamp,
+                    self.metrics.status,
+                    resource.clone(),
+                    ResourceConsumer::LocalMachine,
+                    value,
+                )
+                .with_attr("status", status),
+            );
+        }
+    }
+}
+
+impl Source for BatterySource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        // The batteries can be plugged and unplugged: list them at each poll.
+        for reading in read_batteries(&self.power_supply_dir) {
+            self.push(acc, timestamp, reading);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Creates a power supply directory with the given files.
+    fn power_supply(root: &Path, name: &str, files: &[(&str, &str)]) {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), format!("{content}\n")).unwrap();
+        }
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn read_an_energy_battery() {
+        let root = tempfile::tempdir().unwrap();
+        power_supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("energy_now", "45000000"),
+                ("power_now", "7500000"),
+                ("voltage_now", "12000000"),
+                ("capacity", "87"),
+            ],
+        );
+        let b = read_battery(&root.path().join("BAT0")).unwrap().unwrap();
+        assert_eq!(b.name, "BAT0");
+        assert_eq!(b.status, "Discharging");
+        // 45 Wh
+        assert_close(b.energy, 45.0 * 3600.0);
+        assert_close(b.power, 7.5);
+        assert_close(b.voltage, 12.0);
+        assert_eq!(b.current, None);
+        assert_close(b.capacity, 87.0);
+    }
+
+    #[test]
+    fn compute_the_power_from_the_voltage_and_current() {
+        let root = tempfile::tempdir().unwrap();
+        power_supply(
+            root.path(),
+            "BAT1",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                ("voltage_now", "11000000"),
+                // Negative when the battery discharges, on some drivers.
+                ("current_now", "-500000"),
+            ],
+        );
+        let b = read_battery(&root.path().join("BAT1")).unwrap().unwrap();
+        assert_eq!(b.status, "Discharging");
+        assert_eq!(b.energy, None);
+        assert_close(b.current, -0.5);
+        assert_close(b.power, 5.5);
+    }
+
+    #[test]
+    fn the_power_is_positive() {
+        let root = tempfile::tempdir().unwrap();
+        power_supply(
+            root.path(),
+            "BAT0",
+            &[
+                ("type", "Battery"),
+                ("status", "Discharging"),
+                // Negative when the battery discharges, on some drivers.
+                ("power_now", "-7500000"),
+            ],
+        );
+        let b = read_battery(&root.path().join("BAT0")).unwrap().unwrap();
+        assert_close(b.power, 7.5);
+    }
+
+    #[test]
+    fn read_the_status() {
+        assert_eq!(parse_status("Not charging"), "Not charging");
+        assert_eq!(parse_status("Full"), "Full");
+        assert_eq!(parse_status("Something else"), "Unknown");
+
+        let root = tempfile::tempdir().unwrap();
+        power_supply(root.path(), "BAT0", &[("type", "Battery")]);
+        let b = read_battery(&root.path().join("BAT0")).unwrap().unwrap();
+        assert_eq!(b.status, "Unknown");
+    }
+
+    #[test]
+    fn skip_the_other_power_supplies() {
+        let root = tempfile::tempdir().unwrap();
+        power_supply(root.path(), "AC", &[("type", "Mains"), ("online", "1")]);
+        power_supply(root.path(), "BAT1", &[("type", "Battery"), ("capacity", "50")]);
+        // A battery that cannot be read.
+        power_supply(root.path(), "BAT0", &[("type", "Battery"), ("capacity", "full")]);
+        // A power supply without type.
+        power_supply(root.path(), "ucsi-source-psy-1", &[]);
+
+        assert_eq!(read_battery(&root.path().join("AC")).unwrap(), None);
+        assert!(read_battery(&root.path().join("BAT0")).is_err());
+        assert!(read_battery(&root.path().join("ucsi-source-psy-1")).is_err());
+
+        let batteries = read_batteries(root.path());
+        let names: Vec<&str> = batteries.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["BAT1"]);
+        assert_close(batteries[0].capacity, 50.0);
+    }
+
+    #[test]
+    fn no_power_supply_directory() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(read_batteries(&root.path().join("sys/class/power_supply")), []);
+    }
+}
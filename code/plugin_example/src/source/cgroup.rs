@@ -0,0 +1,483 @@
+//! Source that measures the control groups (cgroup v2), such as containers and systemd slices.
+//!
+//! Each directory of the cgroup hierarchy is a cgroup, measured with its own consumer.
+//! The hierarchy is walked again at each poll: the new cgroups are measured as soon as they appear,
+//! and the removed ones are forgotten.
+//!
+//! See <https://docs.kernel.org/admin-guide/cgroup-v2.html> for the format of the files.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{
+    AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp, WrappedMeasurementValue,
+};
+use alumet::metrics::{MetricId, RawMetricId};
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::{PrefixedUnit, Unit, UnitPrefix};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use super::read_optional;
+
+/// Configuration of the cgroup source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CgroupConfig {
+    /// If true, the cgroups are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `sys/fs/cgroup`.
+    pub root: PathBuf,
+    /// Keys of `memory.stat` to measure. They must be amounts of memory, in bytes.
+    pub memory_stat_keys: Vec<String>,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+            memory_stat_keys: vec![
+                String::from("anon"),
+                String::from("file"),
+                String::from("shmem"),
+                String::from("sock"),
+            ],
+        }
+    }
+}
+
+/// Parses a "flat keyed" file of the cgroup interface, such as `cpu.stat` or `memory.stat`,
+/// where each line is a key and a value separated by a space.
+pub fn parse_flat_keyed(content: &str) -> anyhow::Result<Vec<(&str, u64)>> {
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (key, value) = line.split_once(' ').with_context(|| format!("invalid line: {line}"))?;
+            let value = value.trim().parse().with_context(|| format!("invalid value: {line}"))?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+/// A line of `io.stat`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoStatLine<'a> {
+    /// Device number, `major:minor`.
+    pub device: &'a str,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+}
+
+/// Parses `io.stat`, where each line looks like `8:0 rbytes=1 wbytes=2 rios=3 wios=4 dbytes=0 dios=0`.
+pub fn parse_io_stat(content: &str) -> anyhow::Result<Vec<IoStatLine<'_>>> {
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        let Some(device) = fields.next() else {
+            continue;
+        };
+        let (mut read_bytes, mut write_bytes) = (0, 0);
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .with_context(|| format!("invalid field: {field}"))?;
+            match key {
+                "rbytes" => read_bytes = value.parse().with_context(|| format!("invalid field: {field}"))?,
+                "wbytes" => write_bytes = value.parse().with_context(|| format!("invalid field: {field}"))?,
+                _ => (),
+            }
+        }
+        lines.push(IoStatLine {
+            device,
+            read_bytes,
+            write_bytes,
+        });
+    }
+    Ok(lines)
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 4] = [
+    "example_cgroup_cpu_time",
+    "example_cgroup_memory_current",
+    "example_cgroup_memory_stat",
+    "example_cgroup_io",
+];
+
+/// Measures every cgroup of the hierarchy.
+pub struct CgroupSource {
+    hierarchy: PathBuf,
+    memory_stat_keys: Vec<String>,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    cpu_time: RawMetricId,
+    memory_current: RawMetricId,
+    memory_stat: RawMetricId,
+    io_bytes: RawMetricId,
+}
+
+impl CgroupSource {
+    pub fn new(config: &CgroupConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let microseconds = PrefixedUnit {
+            base_unit: Unit::Second,
+            prefix: UnitPrefix::Micro,
+        };
+        let metrics = Metrics {
+            cpu_time: alumet
+                .create_metric::<u64>(
+                    "example_cgroup_cpu_time",
+                    microseconds,
+                    "time spent by the cgroup in user and system mode",
+                )?
+                .untyped_id(),
+            memory_current: alumet
+                .create_metric::<u64>(
+                    "example_cgroup_memory_current",
+                    Unit::Byte,
+                    "total amount of memory used by the cgroup",
+                )?
+                .untyped_id(),
+            memory_stat: alumet
+                .create_metric::<u64>(
+                    "example_cgroup_memory_stat",
+                    Unit::Byte,
+                    "amount of memory used by the cgroup, by kind",
+                )?
+                .untyped_id(),
+            io_bytes: alumet
+                .create_metric::<u64>(
+                    "example_cgroup_io",
+                    Unit::Byte,
+                    "bytes read from and written to the block devices by the cgroup",
+                )?
+                .untyped_id(),
+        };
+        Ok(Self {
+            hierarchy: config.root.join("sys/fs/cgroup"),
+            memory_stat_keys: config.memory_stat_keys.clone(),
+            metrics,
+        })
+    }
+
+    /// Measures one cgroup. Its files are optional: they depend on the controllers that are enabled.
+    fn measure(&self, dir: &Path, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> anyhow::Result<()> {
+        // The consumer is identified by the path of the cgroup in the hierarchy, such as `/system.slice`.
+        let relative = dir.strip_prefix(&self.hierarchy).unwrap_or(dir);
+        let path = format!("/{}", relative.display());
+        let consumer = ResourceConsumer::ControlGroup { path: path.into() };
+        let point = |metric: RawMetricId, value: u64| {
+            let value = WrappedMeasurementValue::U64(value);
+            MeasurementPoint::new_untyped(timestamp, metric, Resource::LocalMachine, consumer.clone(), value)
+        };
+
+        if let Some(content) = read_optional(&dir.join("cpu.stat"))? {
+            for (key, value) in parse_flat_keyed(&content)? {
+                let state = match key {
+                    "user_usec" => "user",
+                    "system_usec" => "system",
+                    _ => continue,
+                };
+                acc.push(point(self.metrics.cpu_time, value).with_attr("state", state));
+            }
+        }
+        if let Some(content) = read_optional(&dir.join("memory.current"))? {
+            let value = content.trim().parse().context("invalid memory.current")?;
+            acc.push(point(self.metrics.memory_current, value));
+        }
+        if let Some(content) = read_optional(&dir.join("memory.stat"))? {
+            for (key, value) in parse_flat_keyed(&content)? {
+                if self.memory_stat_keys.iter().any(|k| k == key) {
+                    let kind = AttributeValue::String(key.to_owned());
+                    acc.push(point(self.metrics.memory_stat, value).with_attr("kind", kind));
+                }
+            }
+        }
+        if let Some(content) = read_optional(&dir.join("io.stat"))? {
+            for line in parse_io_stat(&content)? {
+                let device = AttributeValue::String(line.device.to_owned());
+                acc.push(
+                    point(self.metrics.io_bytes, line.read_bytes)
+                        .with_attr("device", device.clone())
+                        .with_attr("direction", "read"),
+                );
+                acc.push(
+                    point(self.metrics.io_bytes, line.write_bytes)
+                        .with_attr("device", device)
+                        .with_attr("direction", "write"),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Source for CgroupSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        if !self.hierarchy.is_dir() {
+            return Err(PollError::CanRetry(anyhow!(
+                "cgroup hierarchy not found: {}",
+                self.hierarchy.display()
+            )));
+        }
+        let errors = walk(&self.hierarchy, |dir| self.measure(dir, acc, timestamp));
+        if !errors.is_empty() {
+            return Err(PollError::CanRetry(anyhow!(
+                "failed to measure some cgroups:\n{}",
+                errors.join("\n")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Walks the cgroup hierarchy, starting at the root cgroup, and measures each cgroup with `measure`.
+///
+/// The children of a cgroup are visited even if it cannot be measured. The cgroups that are removed
+/// during the walk are skipped. Returns the other errors, prefixed by the path of their cgroup.
+fn walk(hierarchy: &Path, mut measure: impl FnMut(&Path) -> anyhow::Result<()>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut to_visit = vec![hierarchy.to_owned()];
+    while let Some(dir) = to_visit.pop() {
+        let measured = measure(&dir);
+        let listed = list_children(&dir, &mut to_visit).context("failed to list the child cgroups");
+        for result in [measured, listed] {
+            match result {
+                Ok(()) => (),
+                Err(e) if is_removed(&e) => log::debug!("cgroup {} has been removed during the poll", dir.display()),
+                Err(e) => errors.push(format!("{}: {e:#}", dir.display())),
+            }
+        }
+    }
+    errors
+}
+
+/// Pushes the child cgroups of `dir` to `children`.
+fn list_children(dir: &Path, children: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            children.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the error indicates that the cgroup has been removed.
+fn is_removed(e: &anyhow::Error) -> bool {
+    // ENODEV (no such device) is returned when the cgroup is removed while its file is read.
+    const ENODEV: i32 = 19;
+    match e.downcast_ref::<io::Error>() {
+        Some(e) => e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(ENODEV),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use alumet::measurement::MeasurementBuffer;
+
+    use super::*;
+
+    /// Creates a cgroup hierarchy with the given cgroups, each one with a `cpu.stat` file.
+    fn hierarchy(cgroups: &[&str]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for cgroup in cgroups {
+            let dir = root.path().join(cgroup);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("cpu.stat"), "usage_usec 3\nuser_usec 2\nsystem_usec 1\n").unwrap();
+        }
+        root
+    }
+
+    /// Returns the path of a cgroup, relative to the hierarchy, like the id of its consumer.
+    fn cgroup_path(root: &Path, dir: &Path) -> String {
+        format!("/{}", dir.strip_prefix(root).unwrap().display())
+    }
+
+    #[test]
+    fn walk_the_hierarchy() {
+        let root = hierarchy(&["", "system.slice", "system.slice/cron.service", "user.slice"]);
+        let mut visited = Vec::new();
+        let errors = walk(root.path(), |dir| {
+            visited.push(cgroup_path(root.path(), dir));
+            read_optional(&dir.join("cpu.stat")).map(|_| ())
+        });
+        assert!(errors.is_empty(), "{errors:?}");
+        visited.sort();
+        assert_eq!(
+            visited,
+            ["/", "/system.slice", "/system.slice/cron.service", "/user.slice"]
+        );
+    }
+
+    #[test]
+    fn walk_the_children_of_a_cgroup_that_cannot_be_measured() {
+        let root = hierarchy(&["", "system.slice", "system.slice/cron.service"]);
+        fs::write(root.path().join("system.slice/cpu.stat"), "user_usec x\n").unwrap();
+        let mut visited = Vec::new();
+        let errors = walk(root.path(), |dir| {
+            visited.push(cgroup_path(root.path(), dir));
+            let content = read_optional(&dir.join("cpu.stat"))?.unwrap_or_default();
+            parse_flat_keyed(&content).map(|_| ())
+        });
+        visited.sort();
+        assert_eq!(visited, ["/", "/system.slice", "/system.slice/cron.service"]);
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains("system.slice: invalid value: user_usec x"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn skip_a_cgroup_removed_during_the_walk() {
+        let root = hierarchy(&["", "system.slice", "system.slice/cron.service", "user.slice"]);
+        let mut visited = Vec::new();
+        let errors = walk(root.path(), |dir| {
+            let path = cgroup_path(root.path(), dir);
+            if path == "/system.slice" {
+                // The cgroup disappears between the listing of its parent and its measurement.
+                fs::remove_dir_all(dir).unwrap();
+            }
+            visited.push(path);
+            read_optional(&dir.join("cpu.stat")).map(|_| ())
+        });
+        assert!(errors.is_empty(), "{errors:?}");
+        visited.sort();
+        assert_eq!(visited, ["/", "/system.slice", "/user.slice"]);
+    }
+
+    #[test]
+    fn missing_files_are_optional() {
+        let root = hierarchy(&[""]);
+        assert_eq!(read_optional(&root.path().join("memory.current")).unwrap(), None);
+        let removed = root.path().join("removed");
+        let err = read_optional(&removed.join("memory.current")).unwrap_err();
+        assert!(is_removed(&err));
+    }
+
+    fn source(root: &Path) -> CgroupSource {
+        CgroupSource {
+            hierarchy: root.to_owned(),
+            memory_stat_keys: CgroupConfig::default().memory_stat_keys,
+            metrics: Metrics {
+                cpu_time: RawMetricId::from_u64(0),
+                memory_current: RawMetricId::from_u64(1),
+                memory_stat: RawMetricId::from_u64(2),
+                io_bytes: RawMetricId::from_u64(3),
+            },
+        }
+    }
+
+    /// Measures a cgroup, and returns its points as (metric, value, attributes), with their consumer.
+    fn measure(source: &CgroupSource, dir: &Path) -> Vec<(u64, u64, String, ResourceConsumer)> {
+        let mut buffer = MeasurementBuffer::new();
+        source
+            .measure(dir, &mut buffer.as_accumulator(), Timestamp::now())
+            .unwrap();
+        buffer
+            .iter()
+            .map(|p| {
+                let WrappedMeasurementValue::U64(value) = p.value else {
+                    panic!("unexpected value {:?}", p.value);
+                };
+                let attributes: Vec<String> = p.attributes().map(|(k, v)| format!("{k}={v}")).collect();
+                (p.metric.as_u64(), value, attributes.join(","), p.consumer.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measure_a_cgroup() {
+        let root = hierarchy(&["", "system.slice/cron.service"]);
+        let dir = root.path().join("system.slice/cron.service");
+        fs::write(dir.join("memory.current"), "4096\n").unwrap();
+        fs::write(dir.join("memory.stat"), "anon 1024\nfile 2048\nkernel 512\nshmem 0\n").unwrap();
+        fs::write(
+            dir.join("io.stat"),
+            "8:0 rbytes=10 wbytes=20 rios=1 wios=2 dbytes=0 dios=0\n",
+        )
+        .unwrap();
+
+        let points = measure(&source(root.path()), &dir);
+        let consumer = ResourceConsumer::ControlGroup {
+            path: "/system.slice/cron.service".into(),
+        };
+        assert!(points.iter().all(|p| p.3 == consumer), "{points:?}");
+        let points: Vec<(u64, u64, &str)> = points.iter().map(|(m, v, a, _)| (*m, *v, a.as_str())).collect();
+        assert_eq!(
+            points,
+            [
+                (0, 2, "state=user"),
+                (0, 1, "state=system"),
+                (1, 4096, ""),
+                (2, 1024, "kind=anon"),
+                (2, 2048, "kind=file"),
+                (2, 0, "kind=shmem"),
+                (3, 10, "device=8:0,direction=read"),
+                (3, 20, "device=8:0,direction=write"),
+            ]
+        );
+    }
+
+    #[test]
+    fn measure_the_root_cgroup_without_controllers() {
+        // The root cgroup has no memory.current, and the controllers can be disabled.
+        let root = hierarchy(&[""]);
+        let points = measure(&source(root.path()), root.path());
+        assert_eq!(points.len(), 2);
+        let consumer = ResourceConsumer::ControlGroup { path: "/".into() };
+        assert!(points.iter().all(|p| p.3 == consumer), "{points:?}");
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let root = hierarchy(&[""]);
+        fs::write(root.path().join("memory.current"), "max\n").unwrap();
+        let mut buffer = MeasurementBuffer::new();
+        let err = source(root.path())
+            .measure(root.path(), &mut buffer.as_accumulator(), Timestamp::now())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid memory.current");
+    }
+
+    #[test]
+    fn parse_flat_keyed_files() {
+        let content = "usage_usec 3\nuser_usec 2\n\nsystem_usec 18446744073709551615\n";
+        assert_eq!(
+            parse_flat_keyed(content).unwrap(),
+            [("usage_usec", 3), ("user_usec", 2), ("system_usec", u64::MAX)]
+        );
+        assert_eq!(parse_flat_keyed("").unwrap(), []);
+        assert!(parse_flat_keyed("user_usec\n").is_err());
+        assert!(parse_flat_keyed("user_usec -1\n").is_err());
+        assert!(parse_flat_keyed("user_usec 1 2\n").is_err());
+    }
+
+    #[test]
+    fn parse_io_stat_lines() {
+        let content = "8:0 rbytes=1024 wbytes=2048 rios=1 wios=2 dbytes=0 dios=0\n259:0 rbytes=0 wbytes=512\n";
+        let lines = parse_io_stat(content).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            (lines[0].device, lines[0].read_bytes, lines[0].write_bytes),
+            ("8:0", 1024, 2048)
+        );
+        assert_eq!(
+            (lines[1].device, lines[1].read_bytes, lines[1].write_bytes),
+            ("259:0", 0, 512)
+        );
+    }
+}
@@ -0,0 +1,330 @@
+//! Source that measures the IO of the block devices, from `/proc/diskstats`.
+//!
+//! Each line of the file describes a device: its major and minor numbers, its name, then its counters.
+//! See <https://docs.kernel.org/admin-guide/iostats.html> for the meaning of the fields.
+//!
+//! The partitions are listed alongside the whole disks. A device is a partition if
+//! `/sys/class/block/<name>/partition` exists: by default, the partitions are not measured.
+//!
+//! The sectors of `/proc/diskstats` are always 512-byte units, whatever the block size of the device.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::{PollError, PollRetry};
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::{PrefixedUnit, Unit, UnitPrefix};
+use anyhow::{anyhow, Context};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use super::read_file;
+
+/// Size of a sector in `/proc/diskstats`, in bytes.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// Configuration of the diskstats source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskstatsConfig {
+    /// If true, the block devices are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `proc/diskstats` and `sys/class/block`.
+    pub root: PathBuf,
+    /// If true, the partitions are measured in addition to the whole disks.
+    pub partitions: bool,
+    /// Glob patterns of the devices to ignore, for instance `loop*`.
+    pub exclude: Vec<String>,
+}
+
+impl Default for DiskstatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+            partitions: false,
+            exclude: vec![String::from("loop*"), String::from("ram*"), String::from("zram*")],
+        }
+    }
+}
+
+impl DiskstatsConfig {
+    /// Checks the config, and returns an error that explains the problem if it is invalid.
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.compile_exclude()?;
+        Ok(())
+    }
+
+    fn compile_exclude(&self) -> anyhow::Result<Vec<Pattern>> {
+        self.exclude
+            .iter()
+            .map(|p| Pattern::new(p).with_context(|| format!("invalid device pattern: {p}")))
+            .collect()
+    }
+}
+
+/// The counters of a block device that are useful to the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskStats {
+    pub device: String,
+    /// Number of reads completed successfully.
+    pub reads_completed: u64,
+    /// Number of sectors read.
+    pub sectors_read: u64,
+    /// Number of writes completed successfully.
+    pub writes_completed: u64,
+    /// Number of sectors written.
+    pub sectors_written: u64,
+    /// Time spent doing IO, in milliseconds.
+    pub io_time_ms: u64,
+}
+
+impl DiskStats {
+    /// Number of bytes read. The sectors of `/proc/diskstats` are always 512 bytes long, whatever the device.
+    pub fn bytes_read(&self) -> u64 {
+        self.sectors_read * SECTOR_SIZE
+    }
+
+    /// Number of bytes written.
+    pub fn bytes_written(&self) -> u64 {
+        self.sectors_written * SECTOR_SIZE
+    }
+}
+
+/// Parses `/proc/diskstats`.
+pub fn parse_diskstats(content: &str) -> anyhow::Result<Vec<DiskStats>> {
+    let mut stats = Vec::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_ascii_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        // major minor name, then at least 11 counters (more on recent kernels: discards and flushes).
+        if fields.len() < 14 {
+            return Err(anyhow!("invalid line, expected at least 14 fields: {line}"));
+        }
+        let field = |n: usize| -> anyhow::Result<u64> {
+            fields[n]
+                .parse()
+                .with_context(|| format!("invalid line, field {} is not a number: {line}", n + 1))
+        };
+        stats.push(DiskStats {
+            device: fields[2].to_owned(),
+            reads_completed: field(3)?,
+            sectors_read: field(5)?,
+            writes_completed: field(7)?,
+            sectors_written: field(9)?,
+            io_time_ms: field(12)?,
+        });
+    }
+    Ok(stats)
+}
+
+/// Returns true if the block device is a partition, according to `sys_block_dir` (`/sys/class/block`).
+pub fn is_partition(sys_block_dir: &Path, device: &str) -> bool {
+    // In sysfs, the slashes of the device names (for instance `cciss/c0d0`) are replaced by `!`.
+    sys_block_dir.join(device.replace('/', "!")).join("partition").exists()
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 3] = ["example_disk_bytes", "example_disk_ios", "example_disk_io_time"];
+
+/// Measures the block devices.
+pub struct DiskstatsSource {
+    diskstats_path: PathBuf,
+    sys_block_dir: PathBuf,
+    partitions: bool,
+    exclude: Vec<Pattern>,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    bytes: TypedMetricId<u64>,
+    ios: TypedMetricId<u64>,
+    io_time: TypedMetricId<u64>,
+}
+
+impl DiskstatsSource {
+    pub fn new(config: &DiskstatsConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let milliseconds = PrefixedUnit {
+            base_unit: Unit::Second,
+            prefix: UnitPrefix::Milli,
+        };
+        let metrics = Metrics {
+            bytes: alumet.create_metric::<u64>(
+                "example_disk_bytes",
+                Unit::Byte,
+                "bytes read from or written to the block device",
+            )?,
+            ios: alumet.create_metric::<u64>(
+                "example_disk_ios",
+                Unit::Unity,
+                "reads or writes completed by the block device",
+            )?,
+            io_time: alumet.create_metric::<u64>(
+                "example_disk_io_time",
+                milliseconds,
+                "time spent by the block device doing IO",
+            )?,
+        };
+        Ok(Self {
+            diskstats_path: config.root.join("proc/diskstats"),
+            sys_block_dir: config.root.join("sys/class/block"),
+            partitions: config.partitions,
+            exclude: config.compile_exclude()?,
+            metrics,
+        })
+    }
+
+    fn is_measured(&self, device: &str) -> bool {
+        !self.exclude.iter().any(|p| p.matches(device))
+            && (self.partitions || !is_partition(&self.sys_block_dir, device))
+    }
+}
+
+impl Source for DiskstatsSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let content = read_file(&self.diskstats_path).retry_poll()?;
+        // The devices can change while the file is being read, which could truncate a line: try again later.
+        let stats = parse_diskstats(&content)
+            .with_context(|| format!("invalid file {}", self.diskstats_path.display()))
+            .retry_poll()?;
+        for s in stats.into_iter().filter(|s| self.is_measured(&s.device)) {
+            let point = |metric: TypedMetricId<u64>, value: u64| {
+                MeasurementPoint::new(
+                    timestamp,
+                    metric,
+                    Resource::LocalMachine,
+                    ResourceConsumer::LocalMachine,
+                    value,
+                )
+                .with_attr("device", AttributeValue::String(s.device.clone()))
+            };
+            acc.push(point(self.metrics.bytes, s.bytes_read()).with_attr("direction", "read"));
+            acc.push(point(self.metrics.bytes, s.bytes_written()).with_attr("direction", "write"));
+            acc.push(point(self.metrics.ios, s.reads_completed).with_attr("direction", "read"));
+            acc.push(point(self.metrics.ios, s.writes_completed).with_attr("direction", "write"));
+            acc.push(point(self.metrics.io_time, s.io_time_ms));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const DISKSTATS: &str = "\
+   7       0 loop0 48 0 2134 13 0 0 0 0 0 36 13 0 0 0 0
+ 259       0 nvme0n1 107366 34871 8312578 22807 318254 187021 17012362 331563 0 175312 378421 0 0 0 0 25810 24050
+ 259       1 nvme0n1p1 321 1100 14318 39 2 0 2 0 0 80 39 0 0 0 0 0 0
+   8       0 sda 12 0 96 5 4 1 40 7 0 20 12
+ 104       0 cciss/c0d0 1 0 8 1 1 0 8 1 0 2 2 0 0 0 0
+ 104       1 cciss/c0d0p1 1 0 8 1 1 0 8 1 0 2 2 0 0 0 0
+";
+
+    /// Creates a fake filesystem with `proc/diskstats` and the partitions in `sys/class/block`.
+    fn fake_root(devices: &[&str], partitions: &[&str]) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("proc")).unwrap();
+        fs::write(root.path().join("proc/diskstats"), DISKSTATS).unwrap();
+        for device in devices {
+            fs::create_dir_all(root.path().join("sys/class/block").join(device)).unwrap();
+        }
+        for partition in partitions {
+            let dir = root.path().join("sys/class/block").join(partition);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("partition"), "1\n").unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn parse_kernel_versions() {
+        let stats = parse_diskstats(DISKSTATS).unwrap();
+        let devices: Vec<&str> = stats.iter().map(|s| s.device.as_str()).collect();
+        assert_eq!(
+            devices,
+            ["loop0", "nvme0n1", "nvme0n1p1", "sda", "cciss/c0d0", "cciss/c0d0p1"]
+        );
+        // Linux 5.5 and later: 20 fields.
+        assert_eq!(
+            stats[1],
+            DiskStats {
+                device: String::from("nvme0n1"),
+                reads_completed: 107366,
+                sectors_read: 8312578,
+                writes_completed: 318254,
+                sectors_written: 17012362,
+                io_time_ms: 175312,
+            }
+        );
+        // Before Linux 4.18: 14 fields.
+        assert_eq!(
+            stats[3],
+            DiskStats {
+                device: String::from("sda"),
+                reads_completed: 12,
+                sectors_read: 96,
+                writes_completed: 4,
+                sectors_written: 40,
+                io_time_ms: 20,
+            }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert!(parse_diskstats("   8       0 sda 12 0 96 5 4 1 40 7 0 20\n").is_err());
+        assert!(parse_diskstats("   8       0 sda 12 0 x 5 4 1 40 7 0 20 12\n").is_err());
+        assert_eq!(parse_diskstats("\n").unwrap(), []);
+    }
+
+    #[test]
+    fn sectors_are_512_bytes() {
+        let stats = parse_diskstats(DISKSTATS).unwrap();
+        let sda = &stats[3];
+        assert_eq!(sda.bytes_read(), 96 * 512);
+        assert_eq!(sda.bytes_written(), 40 * 512);
+    }
+
+    #[test]
+    fn find_the_partitions() {
+        let root = fake_root(&["nvme0n1", "sda", "cciss!c0d0"], &["nvme0n1p1", "cciss!c0d0p1"]);
+        let sys_block_dir = root.path().join("sys/class/block");
+        let stats = parse_diskstats(&fs::read_to_string(root.path().join("proc/diskstats")).unwrap()).unwrap();
+        let partitions: Vec<&str> = stats
+            .iter()
+            .map(|s| s.device.as_str())
+            .filter(|d| is_partition(&sys_block_dir, d))
+            .collect();
+        assert_eq!(partitions, ["nvme0n1p1", "cciss/c0d0p1"]);
+        // A device that has disappeared is not a partition.
+        assert!(!is_partition(&sys_block_dir, "sdb1"));
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let config = DiskstatsConfig::default();
+        let exclude = config.compile_exclude().unwrap();
+        let excluded: Vec<&str> = ["loop0", "ram1", "zram0", "sda", "nvme0n1"]
+            .into_iter()
+            .filter(|d| exclude.iter().any(|p| p.matches(d)))
+            .collect();
+        assert_eq!(excluded, ["loop0", "ram1", "zram0"]);
+
+        let config = DiskstatsConfig {
+            exclude: vec![String::from("sd[")],
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+    }
+}
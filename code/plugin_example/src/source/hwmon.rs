@@ -0,0 +1,411 @@
+//! Source that measures the hardware sensors exposed by hwmon and by the thermal zones.
+//!
+//! In `/sys/class/hwmon/hwmon*`, each sensor is a file `<type><n>_input`, with an optional label `<type><n>_label`:
+//! - `temp*_input`: temperature in millidegrees Celsius,
+//! - `power*_input`: power in microwatts,
+//! - `in*_input`: voltage in millivolts,
+//! - `fan*_input`: fan speed in revolutions per minute.
+//!
+//! In `/sys/class/thermal/thermal_zone*`, the file `temp` contains a temperature in millidegrees Celsius,
+//! and the file `type` describes the zone.
+//!
+//! See <https://docs.kernel.org/hwmon/sysfs-interface.html>.
+//! The devices are enumerated at each poll, and the values are converted to the base units (Celsius, Watt, Volt).
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::read_file;
+
+/// Configuration of the hwmon source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HwmonConfig {
+    /// If true, the sensors are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `sys/class/hwmon` and `sys/class/thermal`.
+    pub root: PathBuf,
+}
+
+impl Default for HwmonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+/// The kinds of sensors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorKind {
+    Temperature,
+    Power,
+    Voltage,
+    Fan,
+}
+
+impl SensorKind {
+    /// Factor that converts a value of the sysfs file to the unit of the metric.
+    pub fn scale(self) -> f64 {
+        match self {
+            SensorKind::Temperature => 1e-3,
+            SensorKind::Power => 1e-6,
+            SensorKind::Voltage => 1e-3,
+            SensorKind::Fan => 1.0,
+        }
+    }
+}
+
+/// Parses the name of a hwmon file, and returns the kind of sensor and its prefix (for instance `temp1`)
+/// if it is an input.
+pub fn parse_input_file_name(file_name: &str) -> Option<(SensorKind, &str)> {
+    let prefix = file_name.strip_suffix("_input")?;
+    let kind_end = prefix.find(|c: char| c.is_ascii_digit())?;
+    if !prefix[kind_end..].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let kind = match &prefix[..kind_end] {
+        "temp" => SensorKind::Temperature,
+        "power" => SensorKind::Power,
+        "in" => SensorKind::Voltage,
+        "fan" => SensorKind::Fan,
+        _ => return None,
+    };
+    Some((kind, prefix))
+}
+
+/// A sensor value, converted to the unit of its metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorReading {
+    pub kind: SensorKind,
+    /// Name of the device directory, such as `hwmon0` or `thermal_zone0`.
+    pub device: String,
+    /// Name of the chip, such as `coretemp`. For the thermal zones, this is `thermal_zone`.
+    pub chip: String,
+    /// Label of the sensor, or the prefix of its file if it has no label.
+    pub sensor: String,
+    pub value: f64,
+}
+
+/// Reads the sensors of a hwmon device directory.
+pub fn read_hwmon_device(dir: &Path) -> anyhow::Result<Vec<SensorReading>> {
+    let device = file_name(dir);
+    let chip = read_file(&dir.join("name"))?.trim().to_owned();
+    let mut readings = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("failed to list {}", dir.display()))? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some((kind, prefix)) = file_name.to_str().and_then(parse_input_file_name) else {
+            continue;
+        };
+        // Some sensors are unavailable (for instance, the driver returns ENODATA): skip them.
+        let value = match read_file(&entry.path()).and_then(|v| parse_number(&v)) {
+            Ok(value) => value,
+            Err(e) => {
+                log::debug!("skipping sensor {}: {e:#}", entry.path().display());
+                continue;
+            }
+        };
+        let sensor = match std::fs::read_to_string(dir.join(format!("{prefix}_label"))) {
+            Ok(label) => label.trim().to_owned(),
+            Err(_) => prefix.to_owned(),
+        };
+        readings.push(SensorReading {
+            kind,
+            device: device.clone(),
+            chip: chip.clone(),
+            sensor,
+            value: value * kind.scale(),
+        });
+    }
+    // Sort the sensors to measure them in a stable order.
+    readings.sort_by(|a, b| a.sensor.cmp(&b.sensor));
+    Ok(readings)
+}
+
+/// Reads the temperature of a thermal zone directory.
+pub fn read_thermal_zone(dir: &Path) -> anyhow::Result<SensorReading> {
+    let temp = parse_number(&read_file(&dir.join("temp"))?)?;
+    let zone_type = read_file(&dir.join("type"))?.trim().to_owned();
+    Ok(SensorReading {
+        kind: SensorKind::Temperature,
+        device: file_name(dir),
+        chip: String::from("thermal_zone"),
+        sensor: zone_type,
+        value: temp * SensorKind::Temperature.scale(),
+    })
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 4] = [
+    "example_sensor_temperature",
+    "example_sensor_power",
+    "example_sensor_voltage",
+    "example_sensor_fan_speed",
+];
+
+/// Measures the hwmon sensors and the thermal zones.
+pub struct HwmonSource {
+    hwmon_dir: PathBuf,
+    thermal_dir: PathBuf,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    temperature: TypedMetricId<f64>,
+    power: TypedMetricId<f64>,
+    voltage: TypedMetricId<f64>,
+    fan_speed: TypedMetricId<f64>,
+}
+
+impl HwmonSource {
+    pub fn new(config: &HwmonConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let rpm = Unit::Custom {
+            unique_name: String::from("rpm"),
+            display_name: String::from("RPM"),
+        };
+        let metrics = Metrics {
+            temperature: alumet.create_metric::<f64>(
+                "example_sensor_temperature",
+                Unit::DegreeCelsius,
+                "temperature measured by a hardware sensor",
+            )?,
+            power: alumet.create_metric::<f64>(
+                "example_sensor_power",
+                Unit::Watt,
+                "power measured by a hardware sensor",
+            )?,
+            voltage: alumet.create_metric::<f64>(
+                "example_sensor_voltage",
+                Unit::Volt,
+                "voltage measured by a hardware sensor",
+            )?,
+            fan_speed: alumet.create_metric::<f64>("example_sensor_fan_speed", rpm, "speed of a fan")?,
+        };
+        Ok(Self {
+            hwmon_dir: config.root.join("sys/class/hwmon"),
+            thermal_dir: config.root.join("sys/class/thermal"),
+            metrics,
+        })
+    }
+
+    fn push(&self, acc: &mut MeasurementAccumulator, timestamp: Timestamp, r: SensorReading) {
+        let metric = match r.kind {
+            SensorKind::Temperature => self.metrics.temperature,
+            SensorKind::Power => self.metrics.power,
+            SensorKind::Voltage => self.metrics.voltage,
+            SensorKind::Fan => self.metrics.fan_speed,
+        };
+        let point = MeasurementPoint::new(
+            timestamp,
+            metric,
+            Resource::LocalMachine,
+            ResourceConsumer::LocalMachine,
+            r.value,
+        )
+        .with_attr("device", AttributeValue::String(r.device))
+        .with_attr("chip", AttributeValue::String(r.chip))
+        .with_attr("sensor", AttributeValue::String(r.sensor));
+        acc.push(point);
+    }
+}
+
+impl Source for HwmonSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        // A device that cannot be read is skipped, it does not prevent the other devices from being measured.
+        for dir in list_dirs(&self.hwmon_dir, "hwmon") {
+            match read_hwmon_device(&dir) {
+                Ok(readings) => readings.into_iter().for_each(|r| self.push(acc, timestamp, r)),
+                Err(e) => log::warn!("failed to read hwmon device {}: {e:#}", dir.display()),
+            }
+        }
+        for dir in list_dirs(&self.thermal_dir, "thermal_zone") {
+            match read_thermal_zone(&dir) {
+                Ok(reading) => self.push(acc, timestamp, reading),
+                Err(e) => log::warn!("failed to read thermal zone {}: {e:#}", dir.display()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists the subdirectories of `dir` whose name starts with `prefix`, sorted by name.
+/// Returns nothing if `dir` does not exist.
+fn list_dirs(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_str().is_some_and(|name| name.starts_with(prefix)))
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+/// Parses a number of a sysfs file. Some values, such as temperatures, can be negative.
+fn parse_number(content: &str) -> anyhow::Result<f64> {
+    let content = content.trim();
+    let value: i64 = content.parse().with_context(|| format!("invalid number: {content}"))?;
+    Ok(value as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Creates a device directory with the given files.
+    fn device(dir: &Path, files: &[(&str, &str)]) {
+        fs::create_dir_all(dir).unwrap();
+        for (file, content) in files {
+            fs::write(dir.join(file), format!("{content}\n")).unwrap();
+        }
+    }
+
+    fn assert_reading(r: &SensorReading, kind: SensorKind, sensor: &str, value: f64) {
+        assert_eq!(r.kind, kind);
+        assert_eq!(r.sensor, sensor);
+        assert!((r.value - value).abs() < 1e-9, "{} != {value}", r.value);
+    }
+
+    #[test]
+    fn parse_input_file_names() {
+        assert_eq!(
+            parse_input_file_name("temp1_input"),
+            Some((SensorKind::Temperature, "temp1"))
+        );
+        assert_eq!(
+            parse_input_file_name("power12_input"),
+            Some((SensorKind::Power, "power12"))
+        );
+        assert_eq!(parse_input_file_name("in0_input"), Some((SensorKind::Voltage, "in0")));
+        assert_eq!(parse_input_file_name("fan2_input"), Some((SensorKind::Fan, "fan2")));
+        // Not inputs
+        assert_eq!(parse_input_file_name("temp1_label"), None);
+        assert_eq!(parse_input_file_name("temp1_max"), None);
+        assert_eq!(parse_input_file_name("name"), None);
+        // Invalid names
+        assert_eq!(parse_input_file_name("temp1a_input"), None);
+        assert_eq!(parse_input_file_name("temp_input"), None);
+        assert_eq!(parse_input_file_name("_input"), None);
+        assert_eq!(parse_input_file_name("curr1_input"), None);
+        assert_eq!(parse_input_file_name("1_input"), None);
+    }
+
+    #[test]
+    fn scale_each_kind_of_sensor() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("hwmon3");
+        device(
+            &dir,
+            &[
+                ("name", "nct6775"),
+                ("fan1_input", "1200"),
+                ("in0_input", "1104"),
+                ("power1_input", "15500000"),
+                ("temp1_input", "-5500"),
+            ],
+        );
+        let readings = read_hwmon_device(&dir).unwrap();
+        assert_eq!(readings.len(), 4);
+        assert_reading(&readings[0], SensorKind::Fan, "fan1", 1200.0);
+        assert_reading(&readings[1], SensorKind::Voltage, "in0", 1.104);
+        assert_reading(&readings[2], SensorKind::Power, "power1", 15.5);
+        assert_reading(&readings[3], SensorKind::Temperature, "temp1", -5.5);
+        for r in &readings {
+            assert_eq!(r.device, "hwmon3");
+            assert_eq!(r.chip, "nct6775");
+        }
+    }
+
+    #[test]
+    fn label_or_file_prefix() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("hwmon0");
+        device(
+            &dir,
+            &[
+                ("name", "coretemp"),
+                ("temp1_input", "45000"),
+                ("temp1_label", "Package id 0"),
+                ("temp2_input", "43000"),
+            ],
+        );
+        let readings = read_hwmon_device(&dir).unwrap();
+        assert_eq!(readings.len(), 2);
+        assert_reading(&readings[0], SensorKind::Temperature, "Package id 0", 45.0);
+        assert_reading(&readings[1], SensorKind::Temperature, "temp2", 43.0);
+    }
+
+    #[test]
+    fn skip_unreadable_sensors() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("hwmon1");
+        device(
+            &dir,
+            &[
+                ("name", "acpitz"),
+                ("temp1_input", "27800"),
+                ("temp2_input", "not a number"),
+            ],
+        );
+        // Reading a directory fails, like a sensor whose driver returns an error.
+        fs::create_dir(dir.join("temp3_input")).unwrap();
+        let readings = read_hwmon_device(&dir).unwrap();
+        assert_eq!(readings.len(), 1);
+        assert_reading(&readings[0], SensorKind::Temperature, "temp1", 27.8);
+
+        // A device without a name is skipped by the source.
+        let unnamed = root.path().join("hwmon2");
+        device(&unnamed, &[("temp1_input", "27800")]);
+        assert!(read_hwmon_device(&unnamed).is_err());
+    }
+
+    #[test]
+    fn read_thermal_zones() {
+        let root = tempfile::tempdir().unwrap();
+        let thermal = root.path().join("sys/class/thermal");
+        device(
+            &thermal.join("thermal_zone0"),
+            &[("type", "x86_pkg_temp"), ("temp", "52000")],
+        );
+        device(&thermal.join("thermal_zone1"), &[("type", "acpitz")]);
+        device(&thermal.join("cooling_device0"), &[("type", "Processor")]);
+
+        let dirs = list_dirs(&thermal, "thermal_zone");
+        assert_eq!(dirs, [thermal.join("thermal_zone0"), thermal.join("thermal_zone1")]);
+
+        let zone = read_thermal_zone(&dirs[0]).unwrap();
+        assert_eq!(zone.device, "thermal_zone0");
+        assert_eq!(zone.chip, "thermal_zone");
+        assert_reading(&zone, SensorKind::Temperature, "x86_pkg_temp", 52.0);
+        // The zone without a temperature is skipped by the source.
+        assert!(read_thermal_zone(&dirs[1]).is_err());
+    }
+
+    #[test]
+    fn missing_class_directory() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(list_dirs(&root.path().join("sys/class/hwmon"), "hwmon").is_empty());
+    }
+}
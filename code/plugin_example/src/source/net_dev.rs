@@ -0,0 +1,318 @@
+//! Source that measures the network interfaces, from `/proc/net/dev`.
+//!
+//! See `man 5 proc` for the format of the file.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use alumet::measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::{PollError, PollRetry};
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::{anyhow, Context};
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use super::read_file;
+
+/// Configuration of the network source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetDevConfig {
+    /// If true, the network interfaces are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `proc/net/dev`.
+    pub root: PathBuf,
+    /// Glob patterns of the interfaces to measure, for instance `eth*`.
+    pub include: Vec<String>,
+    /// Glob patterns of the interfaces to ignore, even if they match `include`.
+    pub exclude: Vec<String>,
+}
+
+impl Default for NetDevConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+            include: vec![String::from("*")],
+            exclude: vec![String::from("lo")],
+        }
+    }
+}
+
+impl NetDevConfig {
+    /// Checks the config, and returns an error that explains the problem if it is invalid.
+    pub fn check(&self) -> anyhow::Result<()> {
+        InterfaceFilter::new(self)?;
+        Ok(())
+    }
+}
+
+/// Selects the interfaces with glob patterns.
+pub struct InterfaceFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl InterfaceFilter {
+    pub fn new(config: &NetDevConfig) -> anyhow::Result<Self> {
+        let compile = |patterns: &[String]| -> anyhow::Result<Vec<Pattern>> {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p).with_context(|| format!("invalid interface pattern: {p}")))
+                .collect()
+        };
+        Ok(Self {
+            include: compile(&config.include)?,
+            exclude: compile(&config.exclude)?,
+        })
+    }
+
+    pub fn matches(&self, interface: &str) -> bool {
+        self.include.iter().any(|p| p.matches(interface)) && !self.exclude.iter().any(|p| p.matches(interface))
+    }
+}
+
+/// Counters of a network interface, in one direction (receive or transmit).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    pub bytes: u64,
+    pub packets: u64,
+    pub errors: u64,
+    pub drops: u64,
+}
+
+/// A line of `/proc/net/dev`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceStats {
+    pub interface: String,
+    pub rx: Counters,
+    pub tx: Counters,
+}
+
+/// Parses `/proc/net/dev`.
+pub fn parse_net_dev(content: &str) -> anyhow::Result<Vec<InterfaceStats>> {
+    let mut stats = Vec::new();
+    // The first two lines are the header.
+    for line in content.lines().skip(2) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (interface, values) = line.split_once(':').with_context(|| format!("invalid line: {line}"))?;
+        let values: Vec<u64> = values
+            .split_ascii_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid line: {line}"))?;
+        if values.len() < 16 {
+            return Err(anyhow!("invalid line, expected 16 values: {line}"));
+        }
+        // Receive: bytes packets errs drop fifo frame compressed multicast
+        // Transmit: bytes packets errs drop fifo colls carrier compressed
+        let counters = |offset: usize| Counters {
+            bytes: values[offset],
+            packets: values[offset + 1],
+            errors: values[offset + 2],
+            drops: values[offset + 3],
+        };
+        stats.push(InterfaceStats {
+            interface: interface.trim().to_owned(),
+            rx: counters(0),
+            tx: counters(8),
+        });
+    }
+    Ok(stats)
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 4] = [
+    "example_net_bytes",
+    "example_net_packets",
+    "example_net_errors",
+    "example_net_drops",
+];
+
+/// Measures the network interfaces that match a filter, as counters.
+pub struct NetDevSource {
+    path: PathBuf,
+    filter: InterfaceFilter,
+    metrics: Metrics,
+}
+
+struct Metrics {
+    bytes: TypedMetricId<u64>,
+    packets: TypedMetricId<u64>,
+    errors: TypedMetricId<u64>,
+    drops: TypedMetricId<u64>,
+}
+
+impl NetDevSource {
+    pub fn new(config: &NetDevConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let metrics = Metrics {
+            bytes: alumet.create_metric::<u64>(
+                "example_net_bytes",
+                Unit::Byte,
+                "bytes received or transmitted by the network interface",
+            )?,
+            packets: alumet.create_metric::<u64>(
+                "example_net_packets",
+                Unit::Unity,
+                "packets received or transmitted by the network interface",
+            )?,
+            errors: alumet.create_metric::<u64>(
+                "example_net_errors",
+                Unit::Unity,
+                "errors detected by the network interface",
+            )?,
+            drops: alumet.create_metric::<u64>(
+                "example_net_drops",
+                Unit::Unity,
+                "packets dropped by the network interface",
+            )?,
+        };
+        Ok(Self {
+            path: config.root.join("proc/net/dev"),
+            filter: InterfaceFilter::new(config)?,
+            metrics,
+        })
+    }
+
+    /// The metric of the byte counters.
+    pub fn bytes_metric(&self) -> TypedMetricId<u64> {
+        self.metrics.bytes
+    }
+}
+
+impl Source for NetDevSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let content = read_file(&self.path).retry_poll()?;
+        // The interfaces can change while the file is being read, which could truncate a line: try again later.
+        let stats = parse_net_dev(&content)
+            .with_context(|| format!("invalid file {}", self.path.display()))
+            .retry_poll()?;
+        for s in stats.into_iter().filter(|s| self.filter.matches(&s.interface)) {
+            for (direction, counters) in [("rx", s.rx), ("tx", s.tx)] {
+                let values = [
+                    (self.metrics.bytes, counters.bytes),
+                    (self.metrics.packets, counters.packets),
+                    (self.metrics.errors, counters.errors),
+                    (self.metrics.drops, counters.drops),
+                ];
+                for (metric, value) in values {
+                    let point = MeasurementPoint::new(
+                        timestamp,
+                        metric,
+                        Resource::LocalMachine,
+                        ResourceConsumer::LocalMachine,
+                        value,
+                    )
+                    .with_attr("interface", AttributeValue::String(s.interface.clone()))
+                    .with_attr("direction", direction);
+                    acc.push(point);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  123456     789    0    0    0     0          0         0   123456     789    0    0    0     0       0          0
+  eth0: 98765432  65432    1    2    0     0          0        10 12345678  23456    3    4    0     0       0          0
+wlan0:       0       0    0    0    0     0          0         0        0       0    0    0    0     0       0          0
+";
+
+    fn filter(include: &[&str], exclude: &[&str]) -> InterfaceFilter {
+        let config = NetDevConfig {
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        };
+        InterfaceFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn parse_interfaces() {
+        let stats = parse_net_dev(NET_DEV).unwrap();
+        let names: Vec<&str> = stats.iter().map(|s| s.interface.as_str()).collect();
+        assert_eq!(names, ["lo", "eth0", "wlan0"]);
+        assert_eq!(
+            stats[1],
+            InterfaceStats {
+                interface: String::from("eth0"),
+                rx: Counters {
+                    bytes: 98765432,
+                    packets: 65432,
+                    errors: 1,
+                    drops: 2,
+                },
+                tx: Counters {
+                    bytes: 12345678,
+                    packets: 23456,
+                    errors: 3,
+                    drops: 4,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        let header = NET_DEV.lines().take(2).collect::<Vec<_>>().join("\n");
+        // Truncated line
+        assert!(parse_net_dev(&format!("{header}\n  eth0: 1 2 3 4 5 6 7 8 9\n")).is_err());
+        // No colon after the interface
+        assert!(parse_net_dev(&format!("{header}\n  eth0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16\n")).is_err());
+        // Not a number
+        assert!(parse_net_dev(&format!("{header}\n  eth0: 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 x\n")).is_err());
+        // Only the header
+        assert_eq!(parse_net_dev(&header).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn default_filter_excludes_the_loopback() {
+        let filter = InterfaceFilter::new(&NetDevConfig::default()).unwrap();
+        assert!(!filter.matches("lo"));
+        assert!(filter.matches("eth0"));
+        assert!(filter.matches("wlan0"));
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let filter = filter(&["eth*", "wlan?"], &["eth1*"]);
+        assert!(filter.matches("eth0"));
+        assert!(filter.matches("wlan0"));
+        assert!(!filter.matches("wlan10"));
+        assert!(!filter.matches("eth1"));
+        assert!(!filter.matches("eth10"));
+        assert!(!filter.matches("docker0"));
+    }
+
+    #[test]
+    fn exclude_wins() {
+        let filter = filter(&["eth0"], &["eth*"]);
+        assert!(!filter.matches("eth0"));
+        // Nothing is included by default.
+        assert!(!self::filter(&[], &[]).matches("eth0"));
+    }
+
+    #[test]
+    fn reject_invalid_patterns() {
+        let config = NetDevConfig {
+            include: vec![String::from("eth[")],
+            ..Default::default()
+        };
+        assert!(config.check().is_err());
+    }
+}
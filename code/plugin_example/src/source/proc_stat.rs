@@ -0,0 +1,198 @@
+//! Source that measures the CPU time from `/proc/stat`, per core and for the whole machine.
+//!
+//! See `man 5 proc` for the format of the file.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use alumet::measurement::{MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::{PollError, PollRetry};
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use super::read_file;
+
+/// Configuration of the `/proc/stat` source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcStatConfig {
+    /// If true, the CPU time is measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `proc/stat`.
+    pub root: PathBuf,
+}
+
+impl Default for ProcStatConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+/// The CPU states that are measured, with their column in `/proc/stat`.
+pub const STATES: [(&str, usize); 4] = [("user", 0), ("system", 2), ("idle", 3), ("iowait", 4)];
+
+/// A line of `/proc/stat` that gives the CPU time of a CPU, or of all the CPUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuLine {
+    /// The core, or `None` for the line that aggregates all the cores.
+    pub core: Option<u32>,
+    /// Time spent in each state of [`STATES`], in jiffies (`USER_HZ`).
+    pub times: [u64; STATES.len()],
+}
+
+/// Parses the content of `/proc/stat` and returns its CPU lines.
+pub fn parse_proc_stat(content: &str) -> anyhow::Result<Vec<CpuLine>> {
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        let core = match fields.next() {
+            Some("cpu") => None,
+            Some(name) if name.starts_with("cpu") => {
+                Some(name[3..].parse().with_context(|| format!("invalid CPU name: {name}"))?)
+            }
+            _ => continue,
+        };
+        let values: Vec<u64> = fields
+            .map(|f| f.parse())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid CPU line: {line}"))?;
+        let mut times = [0; STATES.len()];
+        for (i, (state, column)) in STATES.iter().enumerate() {
+            times[i] = *values
+                .get(*column)
+                .with_context(|| format!("missing {state} time in CPU line: {line}"))?;
+        }
+        lines.push(CpuLine { core, times });
+    }
+    if lines.is_empty() {
+        return Err(anyhow!("no CPU line found"));
+    }
+    Ok(lines)
+}
+
+/// Unit of the CPU time in `/proc/stat`: a jiffy is `1/USER_HZ` seconds, usually 10 ms.
+pub fn jiffies() -> Unit {
+    Unit::Custom {
+        unique_name: String::from("jiffy"),
+        display_name: String::from("jiffies"),
+    }
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 1] = ["example_cpu_time"];
+
+/// Measures the CPU time, as a counter per core and per state.
+///
+/// The aggregated CPU time of all the cores is measured with the `LocalMachine` resource.
+pub struct ProcStatSource {
+    path: PathBuf,
+    metric: TypedMetricId<u64>,
+}
+
+impl ProcStatSource {
+    pub fn new(config: &ProcStatConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let metric = alumet.create_metric::<u64>(
+            "example_cpu_time",
+            jiffies(),
+            "time spent by the CPU in each state since boot",
+        )?;
+        Ok(Self {
+            path: config.root.join("proc/stat"),
+            metric,
+        })
+    }
+}
+
+impl Source for ProcStatSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        // The file could be temporarily unavailable: try again at the next poll.
+        let content = read_file(&self.path).retry_poll()?;
+        let lines = parse_proc_stat(&content).with_context(|| format!("invalid file {}", self.path.display()))?;
+        for line in lines {
+            let resource = match line.core {
+                Some(id) => Resource::CpuCore { id },
+                None => Resource::LocalMachine,
+            };
+            for ((state, _), time) in STATES.iter().zip(line.times) {
+                let point = MeasurementPoint::new(
+                    timestamp,
+                    self.metric,
+                    resource.clone(),
+                    ResourceConsumer::LocalMachine,
+                    time,
+                )
+                .with_attr("state", *state);
+                acc.push(point);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_STAT: &str = "\
+cpu  10132153 290696 3084719 46828483 16683 0 25195 0 0 0
+cpu0 1393280 32966 572056 13343292 6130 0 17875 0 0 0
+cpu1 1335231 32808 526418 13470426 3762 0 4316 0 0 0
+intr 1462898 0 0 0
+ctxt 2540580
+btime 1700000000
+processes 26442
+procs_running 1
+procs_blocked 0
+softirq 2341096 0 259417 1 3047 87 0 18735 1012823 0 1046986
+";
+
+    #[test]
+    fn parse_aggregate_and_cores() {
+        let lines = parse_proc_stat(PROC_STAT).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                CpuLine {
+                    core: None,
+                    times: [10132153, 3084719, 46828483, 16683],
+                },
+                CpuLine {
+                    core: Some(0),
+                    times: [1393280, 572056, 13343292, 6130],
+                },
+                CpuLine {
+                    core: Some(1),
+                    times: [1335231, 526418, 13470426, 3762],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_old_kernel() {
+        // Before Linux 2.6, the CPU lines only had 4 columns: there was no iowait.
+        let err = parse_proc_stat("cpu  1 2 3 4\n").unwrap_err();
+        assert_eq!(err.to_string(), "missing iowait time in CPU line: cpu  1 2 3 4");
+        // The 5th column is enough.
+        let lines = parse_proc_stat("cpu  1 2 3 4 5\n").unwrap();
+        assert_eq!(lines[0].times, [1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reject_invalid_lines() {
+        assert!(parse_proc_stat("cpuX 1 2 3 4 5\n").is_err());
+        assert!(parse_proc_stat("cpu0 1 2 x 4 5\n").is_err());
+        assert!(parse_proc_stat("intr 1 2 3\n").is_err());
+        assert!(parse_proc_stat("").is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! Source that measures the resource usage of processes, from `/proc/[pid]`.
+//!
+//! For each process that matches the filter, the source reads:
+//! - `/proc/[pid]/stat`: the CPU time spent in user and system mode,
+//! - `/proc/[pid]/status`: the name, the user and the resident memory (RSS),
+//! - `/proc/[pid]/io`: the number of bytes read from and written to the storage.
+//!
+//! Processes can terminate at any time, including while the source reads their files.
+//! Such processes are ignored. The other errors that concern a single process do not prevent
+//! the source from measuring the other processes: they are reported afterwards, as a non-fatal error.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::{PollError, PollRetry};
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::{anyhow, Context};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::proc_stat::jiffies;
+use super::read_file;
+
+/// Configuration of the process source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessConfig {
+    /// If true, the processes are measured.
+    pub enabled: bool,
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `proc`.
+    pub root: PathBuf,
+    /// Which processes to measure.
+    pub filter: ProcessFilter,
+}
+
+/// Selects the processes to measure. A process must match all the criteria that are set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProcessFilter {
+    /// Regular expression that the name of the process must match.
+    pub name: Option<String>,
+    /// Real user id of the process.
+    pub uid: Option<u32>,
+    /// Cgroup that contains the process, for instance `/system.slice`.
+    /// The process matches if it is in this cgroup or in one of its descendants.
+    pub cgroup: Option<String>,
+}
+
+impl Default for ProcessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+            filter: ProcessFilter::default(),
+        }
+    }
+}
+
+impl ProcessConfig {
+    /// Checks the config, and returns an error that explains the problem if it is invalid.
+    pub fn check(&self) -> anyhow::Result<()> {
+        self.filter.compile()?;
+        Ok(())
+    }
+}
+
+impl ProcessFilter {
+    fn compile(&self) -> anyhow::Result<Option<Regex>> {
+        self.name
+            .as_deref()
+            .map(|name| Regex::new(name).with_context(|| format!("invalid regex for the process name: {name}")))
+            .transpose()
+    }
+}
+
+/// The measurements of a process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    pub name: String,
+    /// Time spent in user mode, in jiffies.
+    pub utime: u64,
+    /// Time spent in system mode, in jiffies.
+    pub stime: u64,
+    /// Resident memory, in bytes. Kernel threads have no resident memory.
+    pub rss: Option<u64>,
+    /// IO counters, only available if we have the permission to read them.
+    pub io: Option<IoStats>,
+}
+
+/// The content of `/proc/[pid]/status` that is useful to the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessStatus {
+    pub name: String,
+    /// Real user id.
+    pub uid: u32,
+    /// Resident memory, in bytes.
+    pub rss: Option<u64>,
+}
+
+/// The content of `/proc/[pid]/io` that is useful to the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoStats {
+    /// Bytes read from the storage layer.
+    pub read_bytes: u64,
+    /// Bytes written to the storage layer.
+    pub write_bytes: u64,
+}
+
+/// Parses `/proc/[pid]/stat` and returns the user and system CPU times.
+pub fn parse_stat(content: &str) -> anyhow::Result<(u64, u64)> {
+    // The second field is the name of the process, in parentheses. It can contain spaces and parentheses,
+    // hence we look for the last closing parenthesis.
+    let end_of_name = content.rfind(')').context("invalid stat: missing process name")?;
+    // The fields after the name start at the third one (state), utime and stime are the 14th and 15th.
+    let fields: Vec<&str> = content[end_of_name + 1..].split_ascii_whitespace().collect();
+    let field = |n: usize| -> anyhow::Result<u64> {
+        let f = fields
+            .get(n - 3)
+            .with_context(|| format!("invalid stat: missing field {n}"))?;
+        f.parse()
+            .with_context(|| format!("invalid stat: field {n} is not a number: {f}"))
+    };
+    Ok((field(14)?, field(15)?))
+}
+
+/// Parses `/proc/[pid]/status`.
+pub fn parse_status(content: &str) -> anyhow::Result<ProcessStatus> {
+    let (mut name, mut uid, mut rss) = (None, None, None);
+    for line in content.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key {
+            "Name" => name = Some(value.to_owned()),
+            // Real, effective, saved set and filesystem uids.
+            "Uid" => {
+                let real = value.split_ascii_whitespace().next().unwrap_or_default();
+                uid = Some(real.parse().with_context(|| format!("invalid uid: {value}"))?);
+            }
+            "VmRSS" => {
+                let kib = value.strip_suffix("kB").unwrap_or(value).trim();
+                let kib: u64 = kib.parse().with_context(|| format!("invalid VmRSS: {value}"))?;
+                rss = Some(kib * 1024);
+            }
+            _ => (),
+        }
+    }
+    Ok(ProcessStatus {
+        name: name.context("invalid status: missing Name")?,
+        uid: uid.context("invalid status: missing Uid")?,
+        rss,
+    })
+}
+
+/// Parses `/proc/[pid]/io`.
+pub fn parse_io(content: &str) -> anyhow::Result<IoStats> {
+    let (mut read_bytes, mut write_bytes) = (None, None);
+    for line in content.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            match key {
+                "read_bytes" => read_bytes = Some(value.parse().with_context(|| format!("invalid {line}"))?),
+                "write_bytes" => write_bytes = Some(value.parse().with_context(|| format!("invalid {line}"))?),
+                _ => (),
+            }
+        }
+    }
+    Ok(IoStats {
+        read_bytes: read_bytes.context("invalid io: missing read_bytes")?,
+        write_bytes: write_bytes.context("invalid io: missing write_bytes")?,
+    })
+}
+
+/// Parses `/proc/[pid]/cgroup` and returns the paths of the cgroups of the process.
+pub fn parse_cgroup(content: &str) -> Vec<&str> {
+    // Each line looks like `hierarchy-id:controllers:path`, the path can contain `:`.
+    content.lines().filter_map(|line| line.splitn(3, ':').nth(2)).collect()
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 3] = [
+    "example_process_cpu_time",
+    "example_process_memory_rss",
+    "example_process_io",
+];
+
+/// Measures the processes that match a filter.
+pub struct ProcessSource {
+    reader: ProcessReader,
+    metrics: Metrics,
+}
+
+/// Reads the measurements of the processes that match a filter.
+struct ProcessReader {
+    proc_dir: PathBuf,
+    name: Option<Regex>,
+    uid: Option<u32>,
+    cgroup: Option<PathBuf>,
+}
+
+struct Metrics {
+    cpu_time: TypedMetricId<u64>,
+    memory_rss: TypedMetricId<u64>,
+    io_bytes: TypedMetricId<u64>,
+}
+
+impl ProcessSource {
+    pub fn new(config: &ProcessConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let metrics = Metrics {
+            cpu_time: alumet.create_metric::<u64>(
+                "example_process_cpu_time",
+                jiffies(),
+                "time spent by the process in user and system mode",
+            )?,
+            memory_rss: alumet.create_metric::<u64>(
+                "example_process_memory_rss",
+                Unit::Byte,
+                "resident memory of the process",
+            )?,
+            io_bytes: alumet.create_metric::<u64>(
+                "example_process_io",
+                Unit::Byte,
+                "bytes read from and written to the storage by the process",
+            )?,
+        };
+        Ok(Self {
+            reader: ProcessReader::new(config)?,
+            metrics,
+        })
+    }
+
+    fn push_stats(&self, acc: &mut MeasurementAccumulator, timestamp: Timestamp, p: ProcessStats) {
+        let consumer = ResourceConsumer::Process { pid: p.pid };
+        let point = |metric: TypedMetricId<u64>, value: u64| {
+            MeasurementPoint::new(timestamp, metric, Resource::LocalMachine, consumer.clone(), value)
+                .with_attr("name", AttributeValue::String(p.name.clone()))
+        };
+        acc.push(point(self.metrics.cpu_time, p.utime).with_attr("state", "user"));
+        acc.push(point(self.metrics.cpu_time, p.stime).with_attr("state", "system"));
+        if let Some(rss) = p.rss {
+            acc.push(point(self.metrics.memory_rss, rss));
+        }
+        if let Some(io) = p.io {
+            acc.push(point(self.metrics.io_bytes, io.read_bytes).with_attr("direction", "read"));
+            acc.push(point(self.metrics.io_bytes, io.write_bytes).with_attr("direction", "write"));
+        }
+    }
+}
+
+impl ProcessReader {
+    fn new(config: &ProcessConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            proc_dir: config.root.join("proc"),
+            name: config.filter.compile()?,
+            uid: config.filter.uid,
+            cgroup: config.filter.cgroup.as_ref().map(PathBuf::from),
+        })
+    }
+
+    /// Reads the measurements of a process, or returns `None` if it does not match the filter.
+    fn read_process(&self, pid: u32) -> anyhow::Result<Option<ProcessStats>> {
+        let dir = self.proc_dir.join(pid.to_string());
+        let status = parse_status(&read_file(&dir.join("status"))?)?;
+        if self.name.as_ref().is_some_and(|re| !re.is_match(&status.name)) || self.uid.is_some_and(|u| u != status.uid)
+        {
+            return Ok(None);
+        }
+        if let Some(parent) = &self.cgroup {
+            let cgroups = read_file(&dir.join("cgroup"))?;
+            // Compare the components of the paths: `/system.slice` contains `/system.slice/x`, not `/system.slice2`.
+            if !parse_cgroup(&cgroups)
+                .iter()
+                .any(|path| Path::new(path).starts_with(parent))
+            {
+                return Ok(None);
+            }
+        }
+        let (utime, stime) = parse_stat(&read_file(&dir.join("stat"))?)?;
+        // Reading the IO counters of the processes of other users requires more privileges.
+        let io = match std::fs::read_to_string(dir.join("io")) {
+            Ok(content) => Some(parse_io(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => None,
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("failed to read {}/io", dir.display()))),
+        };
+        Ok(Some(ProcessStats {
+            pid,
+            name: status.name,
+            utime,
+            stime,
+            rss: status.rss,
+            io,
+        }))
+    }
+}
+
+impl Source for ProcessSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let proc_dir = &self.reader.proc_dir;
+        let entries = std::fs::read_dir(proc_dir)
+            .with_context(|| format!("failed to list {}", proc_dir.display()))
+            .retry_poll()?;
+
+        let mut errors = Vec::new();
+        for entry in entries {
+            let entry = entry
+                .with_context(|| format!("failed to list {}", proc_dir.display()))
+                .retry_poll()?;
+            // The directories of the processes are named after their pid.
+            let Some(pid) = entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) else {
+                continue;
+            };
+            match self.reader.read_process(pid) {
+                Ok(Some(stats)) => self.push_stats(acc, timestamp, stats),
+                Ok(None) => (),
+                Err(e) if has_terminated(&e) => log::debug!("process {pid} has terminated during the poll"),
+                Err(e) => errors.push(format!("process {pid}: {e:#}")),
+            }
+        }
+        if !errors.is_empty() {
+            // The measurements of the other processes have been pushed, only report the errors.
+            return Err(PollError::CanRetry(anyhow!(
+                "failed to measure some processes:\n{}",
+                errors.join("\n")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if the error indicates that the process has terminated.
+fn has_terminated(e: &anyhow::Error) -> bool {
+    // ESRCH (no such process) is returned when the process terminates while its file is read.
+    const ESRCH: i32 = 3;
+    match e.downcast_ref::<io::Error>() {
+        Some(e) => e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(ESRCH),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// The name contains spaces and parentheses, like `(sd-pam)` or a thread named by the user.
+    const STAT: &str =
+        "4242 (my (weird) prog) name) S 1 4242 4242 0 -1 4194560 1000 0 0 0 123 45 0 0 20 0 1 0 100 0 0\n";
+
+    const STATUS: &str = "\
+Name:\tmy prog
+Umask:\t0022
+State:\tS (sleeping)
+Uid:\t1000\t1001\t1002\t1003
+Gid:\t1000\t1000\t1000\t1000
+VmRSS:\t    2048 kB
+Threads:\t1
+";
+
+    const IO: &str = "\
+rchar: 100
+wchar: 200
+syscr: 3
+syscw: 4
+read_bytes: 4096
+write_bytes: 8192
+cancelled_write_bytes: 0
+";
+
+    /// Creates a fake `proc` directory with a process, and returns the root of the filesystem.
+    fn fake_root(pid: u32, cgroup: &str) -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("proc").join(pid.to_string());
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("stat"), STAT).unwrap();
+        fs::write(dir.join("status"), STATUS).unwrap();
+        fs::write(dir.join("io"), IO).unwrap();
+        fs::write(dir.join("cgroup"), format!("0::{cgroup}\n")).unwrap();
+        root
+    }
+
+    fn reader(root: &Path, filter: ProcessFilter) -> ProcessReader {
+        let config = ProcessConfig {
+            root: root.to_owned(),
+            filter,
+            ..Default::default()
+        };
+        ProcessReader::new(&config).unwrap()
+    }
+
+    #[test]
+    fn parse_stat_with_a_strange_name() {
+        assert_eq!(parse_stat(STAT).unwrap(), (123, 45));
+        assert!(parse_stat("4242 (prog) S 1 2 3\n").is_err());
+        assert!(parse_stat("4242 prog S 1 2 3 4 5 6 7 8 9 10 11 12 13\n").is_err());
+    }
+
+    #[test]
+    fn parse_status_and_io() {
+        assert_eq!(
+            parse_status(STATUS).unwrap(),
+            ProcessStatus {
+                name: String::from("my prog"),
+                uid: 1000,
+                rss: Some(2048 * 1024),
+            }
+        );
+        // Kernel threads have no VmRSS.
+        let kthread = parse_status("Name:\tkworker/0:1\nUid:\t0\t0\t0\t0\n").unwrap();
+        assert_eq!(kthread.rss, None);
+        assert!(parse_status("Uid:\t0\t0\t0\t0\n").is_err());
+
+        assert_eq!(
+            parse_io(IO).unwrap(),
+            IoStats {
+                read_bytes: 4096,
+                write_bytes: 8192,
+            }
+        );
+        assert!(parse_io("read_bytes: 1\n").is_err());
+    }
+
+    #[test]
+    fn read_a_process() {
+        let root = fake_root(4242, "/user.slice/session-1.scope");
+        let stats = reader(root.path(), ProcessFilter::default())
+            .read_process(4242)
+            .unwrap();
+        assert_eq!(
+            stats,
+            Some(ProcessStats {
+                pid: 4242,
+                name: String::from("my prog"),
+                utime: 123,
+                stime: 45,
+                rss: Some(2048 * 1024),
+                io: Some(IoStats {
+                    read_bytes: 4096,
+                    write_bytes: 8192,
+                }),
+            })
+        );
+    }
+
+    #[test]
+    fn filter_the_processes() {
+        let root = fake_root(4242, "/system.slice/my.service");
+        let matches = |filter: ProcessFilter| reader(root.path(), filter).read_process(4242).unwrap().is_some();
+        let cgroup = |path: &str| ProcessFilter {
+            cgroup: Some(String::from(path)),
+            ..Default::default()
+        };
+        assert!(matches(cgroup("/system.slice")));
+        assert!(matches(cgroup("/system.slice/my.service")));
+        assert!(matches(cgroup("/")));
+        // The cgroups are compared component by component, not as strings.
+        assert!(!matches(cgroup("/system.sl")));
+        assert!(!matches(cgroup("/user.slice")));
+
+        let name = |re: &str| ProcessFilter {
+            name: Some(String::from(re)),
+            ..Default::default()
+        };
+        assert!(matches(name("^my")));
+        assert!(!matches(name("^prog")));
+
+        let uid = |uid: u32| ProcessFilter {
+            uid: Some(uid),
+            ..Default::default()
+        };
+        // The real uid, not the effective one.
+        assert!(matches(uid(1000)));
+        assert!(!matches(uid(1001)));
+    }
+
+    #[test]
+    fn process_that_terminates_during_the_poll() {
+        let root = fake_root(4242, "/");
+        let reader = reader(root.path(), ProcessFilter::default());
+
+        // The process terminates after its directory has been listed.
+        fs::remove_dir_all(root.path().join("proc/4242")).unwrap();
+        let err = reader.read_process(4242).unwrap_err();
+        assert!(has_terminated(&err), "{err:?}");
+
+        // An invalid file is an error, even if the process still exists.
+        let root = fake_root(4242, "/");
+        fs::write(root.path().join("proc/4242/stat"), "4242 (prog) S\n").unwrap();
+        let reader = self::reader(root.path(), ProcessFilter::default());
+        let err = reader.read_process(4242).unwrap_err();
+        assert!(!has_terminated(&err), "{err:?}");
+    }
+}
@@ -0,0 +1,372 @@
+//! Source that measures the energy consumption of the CPU and memory with RAPL, through the powercap framework.
+//!
+//! Each RAPL domain is a directory `/sys/class/powercap/intel-rapl:<package>[:<subdomain>]` that contains:
+//! - `name`: the name of the domain, such as `package-0`, `core`, `uncore`, `dram` or `psys`,
+//! - `energy_uj`: the energy consumed by the domain, in microjoules,
+//! - `max_energy_range_uj`: the maximum value of `energy_uj`, after which it wraps around to zero.
+//!
+//! See <https://docs.kernel.org/power/powercap/powercap.html>.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use alumet::measurement::{AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp};
+use alumet::metrics::TypedMetricId;
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::plugin::AlumetPluginStart;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::Unit;
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+use super::read_file;
+
+/// Configuration of the RAPL source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaplConfig {
+    /// If true, the energy consumption is measured.
+    pub enabled: bool,
+    /// Time between two measurements. It must be short enough for the counters to wrap around at most once.
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Root of the filesystem that contains `sys/class/powercap`.
+    pub root: PathBuf,
+}
+
+impl Default for RaplConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+/// A RAPL domain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaplDomain {
+    /// Directory of the domain.
+    pub dir: PathBuf,
+    /// Name of the domain, without the package number: `package`, `core`, `uncore`, `dram` or `psys`.
+    pub name: String,
+    /// The CPU package of the domain, or the whole machine for `psys`.
+    pub resource: Resource,
+    /// Maximum value of the energy counter, in microjoules.
+    pub max_energy_range_uj: u64,
+}
+
+/// Finds the RAPL domains in `powercap_dir`.
+pub fn find_domains(powercap_dir: &Path) -> anyhow::Result<Vec<RaplDomain>> {
+    let entries =
+        std::fs::read_dir(powercap_dir).with_context(|| format!("failed to list {}", powercap_dir.display()))?;
+    let mut domains = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(dir_name) = file_name.to_str() else {
+            continue;
+        };
+        // Directories of the domains: intel-rapl:0, intel-rapl:0:1, etc.
+        // The directory `intel-rapl` itself is the control type, it has no counter.
+        let Some(numbers) = dir_name.strip_prefix("intel-rapl:") else {
+            continue;
+        };
+        let package: u32 = numbers
+            .split(':')
+            .next()
+            .and_then(|n| n.parse().ok())
+            .with_context(|| format!("invalid RAPL domain directory: {dir_name}"))?;
+
+        let dir = entry.path();
+        let name = read_file(&dir.join("name"))?.trim().to_owned();
+        let max_energy_range_uj = read_u64(&dir.join("max_energy_range_uj"))?;
+        let (name, resource) = match name.split_once('-') {
+            // The name of a package domain contains its number, for instance `package-0`.
+            Some(("package", _)) => (String::from("package"), Resource::CpuPackage { id: package }),
+            _ if name == "psys" => (name, Resource::LocalMachine),
+            _ => (name, Resource::CpuPackage { id: package }),
+        };
+        domains.push(RaplDomain {
+            dir,
+            name,
+            resource,
+            max_energy_range_uj,
+        });
+    }
+    // Sort the domains to measure them in a stable order.
+    domains.sort_by(|a, b| a.dir.cmp(&b.dir));
+    Ok(domains)
+}
+
+/// Returns the energy consumed between two values of a counter that wraps around after `max`.
+///
+/// The counter is assumed to have wrapped around at most once.
+pub fn energy_increase(previous: u64, latest: u64, max: u64) -> u64 {
+    if latest >= previous {
+        latest - previous
+    } else {
+        // The counter has reached `max` and restarted from zero.
+        max.saturating_sub(previous) + latest
+    }
+}
+
+/// Names of the metrics created by the source.
+pub const METRICS: [&str; 1] = ["example_rapl_energy"];
+
+/// Measures the energy consumed by each RAPL domain, as a counter in Joules that never wraps around.
+///
+/// The counters start at zero when the source starts.
+pub struct RaplSource {
+    metric: TypedMetricId<f64>,
+    domains: Vec<DomainCounter>,
+}
+
+/// A domain and the state of its counter.
+struct DomainCounter {
+    domain: RaplDomain,
+    /// Last value of `energy_uj`.
+    previous_uj: Option<u64>,
+    /// Energy consumed since the source started, in microjoules.
+    total_uj: u64,
+}
+
+impl DomainCounter {
+    fn new(domain: RaplDomain) -> Self {
+        Self {
+            domain,
+            previous_uj: None,
+            total_uj: 0,
+        }
+    }
+
+    /// Reads the counter of the domain, and returns the energy consumed since the first update, in microjoules.
+    fn update(&mut self) -> anyhow::Result<u64> {
+        let latest = read_u64(&self.domain.dir.join("energy_uj"))?;
+        if let Some(previous) = self.previous_uj {
+            self.total_uj += energy_increase(previous, latest, self.domain.max_energy_range_uj);
+        }
+        self.previous_uj = Some(latest);
+        Ok(self.total_uj)
+    }
+}
+
+impl RaplSource {
+    pub fn new(config: &RaplConfig, alumet: &mut AlumetPluginStart) -> anyhow::Result<Self> {
+        let powercap_dir = config.root.join("sys/class/powercap");
+        let domains = find_domains(&powercap_dir)?;
+        if domains.is_empty() {
+            return Err(anyhow!("no RAPL domain found in {}", powercap_dir.display()));
+        }
+        let metric = alumet.create_metric::<f64>(
+            "example_rapl_energy",
+            Unit::Joule,
+            "energy consumed by the RAPL domain since the source started",
+        )?;
+        let domains = domains.into_iter().map(DomainCounter::new).collect();
+        Ok(Self { metric, domains })
+    }
+
+    /// The metric of the energy counters.
+    pub fn energy_metric(&self) -> TypedMetricId<f64> {
+        self.metric
+    }
+}
+
+impl Source for RaplSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        let (totals, errors) = update_counters(&mut self.domains);
+        for (domain, total_uj) in totals {
+            let joules = total_uj as f64 / 1e6;
+            let point = MeasurementPoint::new(
+                timestamp,
+                self.metric,
+                domain.resource.clone(),
+                ResourceConsumer::LocalMachine,
+                joules,
+            )
+            .with_attr("domain", AttributeValue::String(domain.name.clone()));
+            acc.push(point);
+        }
+        if !errors.is_empty() {
+            // The measurements of the other domains have been pushed, only report the errors.
+            return Err(PollError::CanRetry(anyhow!(
+                "failed to measure some RAPL domains:\n{}",
+                errors.join("\n")
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Updates the counters, and returns the energy of each domain (in microjoules) that could be read.
+/// A domain that cannot be read does not prevent the other ones from being measured: its error is returned.
+fn update_counters(counters: &mut [DomainCounter]) -> (Vec<(&RaplDomain, u64)>, Vec<String>) {
+    let mut totals = Vec::new();
+    let mut errors = Vec::new();
+    for counter in counters {
+        match counter.update() {
+            Ok(total_uj) => totals.push((&counter.domain, total_uj)),
+            Err(e) => errors.push(format!("domain {}: {e:#}", counter.domain.dir.display())),
+        }
+    }
+    (totals, errors)
+}
+
+fn read_u64(path: &Path) -> anyhow::Result<u64> {
+    let content = read_file(path)?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid number in {}: {content}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A fake powercap directory, whose counters are advanced by the tests.
+    struct FakePowercap {
+        dir: tempfile::TempDir,
+    }
+
+    impl FakePowercap {
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            // The control type, which is not a domain.
+            fs::create_dir(dir.path().join("intel-rapl")).unwrap();
+            fs::write(dir.path().join("intel-rapl/enabled"), "1\n").unwrap();
+            Self { dir }
+        }
+
+        fn add_domain(&self, dir_name: &str, name: &str, max_energy_range_uj: u64) {
+            let dir = self.dir.path().join(dir_name);
+            fs::create_dir(&dir).unwrap();
+            fs::write(dir.join("name"), format!("{name}\n")).unwrap();
+            fs::write(dir.join("max_energy_range_uj"), format!("{max_energy_range_uj}\n")).unwrap();
+            self.set_energy(dir_name, 0);
+        }
+
+        fn set_energy(&self, dir_name: &str, energy_uj: u64) {
+            fs::write(
+                self.dir.path().join(dir_name).join("energy_uj"),
+                format!("{energy_uj}\n"),
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn find_the_domains() {
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:1", "package-1", 1000);
+        powercap.add_domain("intel-rapl:0", "package-0", 1000);
+        powercap.add_domain("intel-rapl:0:0", "core", 2000);
+        powercap.add_domain("intel-rapl:0:1", "dram", 3000);
+        powercap.add_domain("intel-rapl:2", "psys", 4000);
+
+        let domains = find_domains(powercap.dir.path()).unwrap();
+        let found: Vec<(&str, &str, Resource, u64)> = domains
+            .iter()
+            .map(|d| {
+                let dir_name = d.dir.file_name().unwrap().to_str().unwrap();
+                (dir_name, d.name.as_str(), d.resource.clone(), d.max_energy_range_uj)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("intel-rapl:0", "package", Resource::CpuPackage { id: 0 }, 1000),
+                ("intel-rapl:0:0", "core", Resource::CpuPackage { id: 0 }, 2000),
+                ("intel-rapl:0:1", "dram", Resource::CpuPackage { id: 0 }, 3000),
+                ("intel-rapl:1", "package", Resource::CpuPackage { id: 1 }, 1000),
+                ("intel-rapl:2", "psys", Resource::LocalMachine, 4000),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_invalid_domains() {
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:x", "package-0", 1000);
+        assert!(find_domains(powercap.dir.path()).is_err());
+
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:0", "package-0", 1000);
+        fs::remove_file(powercap.dir.path().join("intel-rapl:0/max_energy_range_uj")).unwrap();
+        assert!(find_domains(powercap.dir.path()).is_err());
+
+        let missing = powercap.dir.path().join("missing");
+        assert!(find_domains(&missing).is_err());
+    }
+
+    #[test]
+    fn increase_with_wraparound() {
+        assert_eq!(energy_increase(100, 250, 1000), 150);
+        assert_eq!(energy_increase(100, 100, 1000), 0);
+        // From 900 to 1000, then from 0 to 50.
+        assert_eq!(energy_increase(900, 50, 1000), 150);
+        assert_eq!(energy_increase(1000, 0, 1000), 0);
+        // A value above the maximum does not underflow.
+        assert_eq!(energy_increase(1200, 50, 1000), 50);
+    }
+
+    #[test]
+    fn count_the_energy_across_wraparounds() {
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:0", "package-0", 1_000_000);
+        powercap.set_energy("intel-rapl:0", 400_000);
+        let domain = find_domains(powercap.dir.path()).unwrap().remove(0);
+        let mut counter = DomainCounter::new(domain);
+
+        // The counter starts at zero, whatever the value of energy_uj.
+        assert_eq!(counter.update().unwrap(), 0);
+        powercap.set_energy("intel-rapl:0", 900_000);
+        assert_eq!(counter.update().unwrap(), 500_000);
+        powercap.set_energy("intel-rapl:0", 200_000);
+        assert_eq!(counter.update().unwrap(), 800_000);
+        powercap.set_energy("intel-rapl:0", 200_000);
+        assert_eq!(counter.update().unwrap(), 800_000);
+        powercap.set_energy("intel-rapl:0", 100_000);
+        assert_eq!(counter.update().unwrap(), 1_700_000);
+    }
+
+    #[test]
+    fn measure_the_other_domains_when_one_cannot_be_read() {
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:0", "package-0", 1000);
+        powercap.add_domain("intel-rapl:0:0", "core", 1000);
+        powercap.add_domain("intel-rapl:1", "package-1", 1000);
+        let domains = find_domains(powercap.dir.path()).unwrap();
+        let mut counters: Vec<DomainCounter> = domains.into_iter().map(DomainCounter::new).collect();
+        update_counters(&mut counters);
+
+        powercap.set_energy("intel-rapl:0", 100);
+        fs::remove_file(powercap.dir.path().join("intel-rapl:0:0/energy_uj")).unwrap();
+        powercap.set_energy("intel-rapl:1", 300);
+        let (totals, errors) = update_counters(&mut counters);
+        let totals: Vec<(&str, u64)> = totals.iter().map(|(d, uj)| (d.name.as_str(), *uj)).collect();
+        assert_eq!(totals, [("package", 100), ("package", 300)]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("intel-rapl:0:0"), "{errors:?}");
+    }
+
+    #[test]
+    fn keep_the_counter_when_energy_cannot_be_read() {
+        let powercap = FakePowercap::new();
+        powercap.add_domain("intel-rapl:0", "package-0", 1000);
+        let domain = find_domains(powercap.dir.path()).unwrap().remove(0);
+        let mut counter = DomainCounter::new(domain);
+        assert_eq!(counter.update().unwrap(), 0);
+        powercap.set_energy("intel-rapl:0", 300);
+        assert_eq!(counter.update().unwrap(), 300);
+
+        fs::write(powercap.dir.path().join("intel-rapl:0/energy_uj"), "").unwrap();
+        assert!(counter.update().is_err());
+        powercap.set_energy("intel-rapl:0", 500);
+        assert_eq!(counter.update().unwrap(), 500);
+    }
+}
@@ -0,0 +1,712 @@
+//! Source that replays the measurements recorded by the output of the example plugin.
+//!
+//! The recording can be in the text, CSV or JSON Lines format. The points keep their original timestamps,
+//! but the replay can be faster than the recording. This allows to test the transforms with real data,
+//! without the hardware that produced it.
+//!
+//! The replayed points have the attribute `replayed = true`, to distinguish them from the live measurements.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use alumet::measurement::{
+    AttributeValue, MeasurementAccumulator, MeasurementPoint, Timestamp, WrappedMeasurementType,
+    WrappedMeasurementValue,
+};
+use alumet::metrics::RawMetricId;
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::resources::{Resource, ResourceConsumer};
+use alumet::units::{PrefixedUnit, Unit, UnitPrefix};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of the replay source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayConfig {
+    /// If true, the recording is replayed.
+    pub enabled: bool,
+    /// Path of the recording.
+    pub path: PathBuf,
+    /// Format of the recording.
+    pub format: ReplayFormat,
+    /// How fast the points are replayed.
+    pub pace: ReplayPace,
+    /// Time between each activation of the replay source.
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// Metrics that are not replayed, for instance because they are computed by a transform.
+    pub skip_metrics: Vec<String>,
+}
+
+/// Format of a recording: the formats of the output that can be parsed back.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ReplayFormat {
+    Text,
+    Csv,
+    JsonLines,
+}
+
+/// How fast the points are replayed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum ReplayPace {
+    /// Replay the points with the same delays as in the recording.
+    Original,
+    /// Replay the points `factor` times faster than the recording.
+    Accelerated { factor: f64 },
+    /// Replay at most `max_points_per_poll` points each time the source is polled, regardless of their timestamps.
+    AsFastAsPossible { max_points_per_poll: usize },
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: PathBuf::from("alumet-tutorial-output.txt"),
+            format: ReplayFormat::Text,
+            pace: ReplayPace::Original,
+            poll_interval: Duration::from_millis(100),
+            skip_metrics: vec![
+                String::from("example_source_call_diff"),
+                String::from("example_source_call_rate"),
+            ],
+        }
+    }
+}
+
+/// A measurement point read from a recording.
+///
+/// The metric is identified by its name, because the ids of the metrics change from one run of Alumet to another.
+#[derive(Debug, Clone)]
+pub struct RecordedPoint {
+    pub timestamp: SystemTime,
+    pub metric: String,
+    pub value: WrappedMeasurementValue,
+    pub resource: Resource,
+    pub consumer: ResourceConsumer,
+    pub attributes: Vec<(String, String)>,
+    /// Unit of the metric, if the format records it (only JSON Lines does).
+    pub unit: Option<PrefixedUnit>,
+}
+
+/// Reads a recording and returns its points, sorted by timestamp.
+pub fn read_recording(path: &Path, format: ReplayFormat) -> anyhow::Result<Vec<RecordedPoint>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read recording {}", path.display()))?;
+    let mut points = match format {
+        ReplayFormat::Text => parse_text(&content),
+        ReplayFormat::Csv => parse_csv(&content),
+        ReplayFormat::JsonLines => parse_json_lines(&content),
+    }
+    .with_context(|| format!("invalid recording {}", path.display()))?;
+    // The sort is stable: the points that have the same timestamp stay in the same order.
+    points.sort_by_key(|p| p.timestamp);
+    Ok(points)
+}
+
+/// A metric of a recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedMetric {
+    pub name: String,
+    pub value_type: WrappedMeasurementType,
+    pub unit: PrefixedUnit,
+}
+
+/// Returns the metrics of the recorded points, in order of appearance, with the type of their values and their unit.
+///
+/// The text and CSV formats do not contain the type of the metrics: a metric is considered to be
+/// a `u64` metric, unless one of its values is not an integer. They do not contain the units either:
+/// the metrics of these formats have no unit.
+pub fn recorded_metrics(points: &[RecordedPoint]) -> anyhow::Result<Vec<RecordedMetric>> {
+    let mut metrics: Vec<RecordedMetric> = Vec::new();
+    for p in points {
+        let is_float = matches!(p.value, WrappedMeasurementValue::F64(_));
+        let unit = p.unit.clone().unwrap_or_else(|| Unit::Unity.into());
+        match metrics.iter_mut().find(|m| m.name == p.metric) {
+            Some(m) => {
+                if is_float {
+                    m.value_type = WrappedMeasurementType::F64;
+                }
+                if m.unit != unit {
+                    return Err(anyhow!(
+                        "metric {} is recorded with two units: {} and {}",
+                        p.metric,
+                        m.unit.unique_name(),
+                        unit.unique_name()
+                    ));
+                }
+            }
+            None => {
+                let value_type = if is_float {
+                    WrappedMeasurementType::F64
+                } else {
+                    WrappedMeasurementType::U64
+                };
+                metrics.push(RecordedMetric {
+                    name: p.metric.clone(),
+                    value_type,
+                    unit,
+                });
+            }
+        }
+    }
+    Ok(metrics)
+}
+
+/// Parses the unique name of a unit, as written by the JSON Lines output.
+///
+/// The name is made of an optional prefix, such as `milli`, followed by the name of the base unit.
+/// A name that does not end with a standard unit is kept as a custom unit.
+pub fn parse_unit(name: &str) -> PrefixedUnit {
+    const PREFIXES: [&str; 6] = ["nano", "micro", "milli", "kilo", "mega", "giga"];
+    if let Ok(base_unit) = name.parse::<Unit>() {
+        return base_unit.into();
+    }
+    for prefix in PREFIXES {
+        if let Some(base) = name.strip_prefix(prefix) {
+            if let (Ok(base_unit), Ok(prefix)) = (base.parse::<Unit>(), prefix.parse::<UnitPrefix>()) {
+                return PrefixedUnit { base_unit, prefix };
+            }
+        }
+    }
+    Unit::Custom {
+        unique_name: name.to_owned(),
+        display_name: name.to_owned(),
+    }
+    .into()
+}
+
+/// Pushes recorded points to the measurement pipeline.
+pub struct ReplaySource {
+    points: VecDeque<RecordedPoint>,
+    /// Id and type of the metrics, by name.
+    metrics: HashMap<String, (RawMetricId, WrappedMeasurementType)>,
+    pace: ReplayPace,
+    /// Timestamp of the first recorded point.
+    first_timestamp: Option<SystemTime>,
+    /// When the replay started, at the first poll.
+    start: Option<Instant>,
+}
+
+impl ReplaySource {
+    /// Creates a source that replays `points`.
+    ///
+    /// `metrics` must contain the id and type of the metric of each point.
+    /// The integer values are converted to floats if their metric is a `f64` metric.
+    pub fn new(
+        points: Vec<RecordedPoint>,
+        metrics: HashMap<String, (RawMetricId, WrappedMeasurementType)>,
+        pace: ReplayPace,
+    ) -> anyhow::Result<Self> {
+        if let ReplayPace::Accelerated { factor } = pace {
+            if !(factor > 0.0 && factor.is_finite()) {
+                return Err(anyhow!("invalid acceleration factor {factor}: it must be positive"));
+            }
+        }
+        for p in &points {
+            match (metrics.get(&p.metric), &p.value) {
+                (None, _) => return Err(anyhow!("no metric for the recorded points of {}", p.metric)),
+                (Some((_, WrappedMeasurementType::U64)), WrappedMeasurementValue::F64(x)) => {
+                    return Err(anyhow!(
+                        "metric {} expects integers, but {x} has been recorded",
+                        p.metric
+                    ))
+                }
+                _ => (),
+            }
+        }
+        Ok(Self {
+            first_timestamp: points.first().map(|p| p.timestamp),
+            points: VecDeque::from(points),
+            metrics,
+            pace,
+            start: None,
+        })
+    }
+
+    /// Returns the points that must be replayed, `elapsed` after the beginning of the replay.
+    ///
+    /// The points are replayed in order: each call returns the points that follow the previous ones.
+    fn next_points(&mut self, elapsed: Duration) -> Vec<MeasurementPoint> {
+        let mut points = Vec::new();
+        while let Some(next) = self.points.front() {
+            if !self.is_due(next, points.len(), elapsed) {
+                break;
+            }
+            let p = self.points.pop_front().unwrap();
+            points.push(self.to_measurement(p));
+        }
+        points
+    }
+
+    /// Returns true if the next point should be replayed now.
+    ///
+    /// `replayed` is the number of points that have already been replayed by the current poll,
+    /// and `elapsed` the time since the beginning of the replay.
+    fn is_due(&self, next: &RecordedPoint, replayed: usize, elapsed: Duration) -> bool {
+        let first_timestamp = self.first_timestamp.unwrap_or(next.timestamp);
+        let recorded_delay = next.timestamp.duration_since(first_timestamp).unwrap_or(Duration::ZERO);
+        match self.pace {
+            ReplayPace::Original => recorded_delay <= elapsed,
+            ReplayPace::Accelerated { factor } => recorded_delay.as_secs_f64() <= elapsed.as_secs_f64() * factor,
+            ReplayPace::AsFastAsPossible { max_points_per_poll } => replayed < max_points_per_poll,
+        }
+    }
+
+    fn to_measurement(&self, p: RecordedPoint) -> MeasurementPoint {
+        let (metric, value_type) = &self.metrics[&p.metric];
+        let value = match (p.value, value_type) {
+            (WrappedMeasurementValue::U64(x), WrappedMeasurementType::F64) => WrappedMeasurementValue::F64(x as f64),
+            (value, _) => value,
+        };
+        let mut point =
+            MeasurementPoint::new_untyped(Timestamp::from(p.timestamp), *metric, p.resource, p.consumer, value);
+        for (key, value) in p.attributes {
+            // The recording can itself come from a replay.
+            if key != "replayed" {
+                point = point.with_attr(key, AttributeValue::String(value));
+            }
+        }
+        point.with_attr("replayed", AttributeValue::Bool(true))
+    }
+}
+
+impl Source for ReplaySource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, _timestamp: Timestamp) -> Result<(), PollError> {
+        if self.points.is_empty() {
+            return Ok(()); // the replay is finished
+        }
+        // The replay starts at the first poll.
+        let elapsed = self.start.get_or_insert_with(Instant::now).elapsed();
+        for point in self.next_points(elapsed) {
+            acc.push(point);
+        }
+        if self.points.is_empty() {
+            log::info!("The replay is finished.");
+        }
+        Ok(())
+    }
+}
+
+/// Parses a recording in the text format, where each line looks like:
+///
+/// ```text
+/// SystemTime { tv_sec: 1700000000, tv_nsec: 0 }: metric = 1; resource = local_machine/; consumer = local_machine/; attributes = [key='value']
+/// ```
+pub fn parse_text(content: &str) -> anyhow::Result<Vec<RecordedPoint>> {
+    let mut points = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let point = parse_text_line(line).with_context(|| format!("line {}", i + 1))?;
+        points.push(point);
+    }
+    Ok(points)
+}
+
+fn parse_text_line(line: &str) -> anyhow::Result<RecordedPoint> {
+    let invalid = || anyhow!("invalid line: {line}");
+    let rest = line.strip_prefix("SystemTime { tv_sec: ").ok_or_else(invalid)?;
+    let (secs, rest) = rest.split_once(", tv_nsec: ").ok_or_else(invalid)?;
+    let (nanos, rest) = rest.split_once(" }: ").ok_or_else(invalid)?;
+    let (metric, rest) = rest.split_once(" = ").ok_or_else(invalid)?;
+    let (value, rest) = rest.split_once("; resource = ").ok_or_else(invalid)?;
+    let (resource, rest) = rest.split_once("; consumer = ").ok_or_else(invalid)?;
+    let (consumer, rest) = rest.split_once("; attributes = [").ok_or_else(invalid)?;
+    let attributes = rest.strip_suffix(']').ok_or_else(invalid)?;
+
+    let secs: i64 = secs.parse().context("invalid tv_sec")?;
+    let nanos: u32 = nanos.parse().context("invalid tv_nsec")?;
+    let timestamp = if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(u64::from(nanos))
+    };
+    let (resource_kind, resource_id) = resource.split_once('/').unwrap_or((resource, ""));
+    let (consumer_kind, consumer_id) = consumer.split_once('/').unwrap_or((consumer, ""));
+
+    Ok(RecordedPoint {
+        timestamp,
+        metric: metric.to_owned(),
+        value: parse_value(value)?,
+        resource: Resource::parse(resource_kind.to_owned(), resource_id.to_owned())?,
+        consumer: ResourceConsumer::parse(consumer_kind.to_owned(), consumer_id.to_owned())?,
+        attributes: parse_text_attributes(attributes)?,
+        unit: None,
+    })
+}
+
+/// Parses the attributes of the text format: `key='value'` pairs separated by `,`.
+///
+/// The values are not escaped by the text format: a value that contains `',` cannot be parsed correctly.
+fn parse_text_attributes(mut s: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    while !s.is_empty() {
+        let (key, rest) = s.split_once("='").with_context(|| format!("invalid attributes: {s}"))?;
+        let (value, rest) = match rest.split_once("',") {
+            Some(split) => split,
+            None => (
+                rest.strip_suffix('\'')
+                    .with_context(|| format!("invalid attributes: {s}"))?,
+                "",
+            ),
+        };
+        attributes.push((key.to_owned(), value.to_owned()));
+        s = rest;
+    }
+    Ok(attributes)
+}
+
+/// Parses a recording in the CSV format. The columns are found by their name, in the header.
+pub fn parse_csv(content: &str) -> anyhow::Result<Vec<RecordedPoint>> {
+    let mut records = parse_csv_records(content)?.into_iter();
+    let header = records.next().context("missing CSV header")?;
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|c| c == name)
+            .with_context(|| format!("missing CSV column {name}"))
+    };
+    let timestamp = column("timestamp")?;
+    let metric = column("metric")?;
+    let value = column("value")?;
+    let resource_kind = column("resource_kind")?;
+    let resource_id = column("resource_id")?;
+    let consumer_kind = column("consumer_kind")?;
+    let consumer_id = column("consumer_id")?;
+    let attributes = column("attributes")?;
+    // The attributes can also be flattened, with one column per attribute, between consumer_id and attributes.
+    let flattened = consumer_id + 1..attributes;
+
+    let mut points = Vec::new();
+    for (i, record) in records.enumerate() {
+        let parse_record = || -> anyhow::Result<RecordedPoint> {
+            if record.len() != header.len() {
+                return Err(anyhow!("expected {} fields, found {}", header.len(), record.len()));
+            }
+            let mut point_attributes: Vec<(String, String)> = flattened
+                .clone()
+                .filter(|c| !record[*c].is_empty())
+                .map(|c| (header[c].clone(), record[c].clone()))
+                .collect();
+            point_attributes.extend(parse_csv_attributes(&record[attributes]));
+            Ok(RecordedPoint {
+                timestamp: parse_timestamp(&record[timestamp])?,
+                metric: record[metric].clone(),
+                value: parse_value(&record[value])?,
+                resource: Resource::parse(record[resource_kind].clone(), record[resource_id].clone())?,
+                consumer: ResourceConsumer::parse(record[consumer_kind].clone(), record[consumer_id].clone())?,
+                attributes: point_attributes,
+                unit: None,
+            })
+        };
+        // The header is the first record.
+        points.push(parse_record().with_context(|| format!("CSV record {}", i + 2))?);
+    }
+    Ok(points)
+}
+
+/// Splits a CSV file into records and fields, according to RFC 4180.
+fn parse_csv_records(content: &str) -> anyhow::Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => (),
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Parses the `attributes` column of the CSV format: `key=value` pairs separated by `;`,
+/// where `\`, `;` and `=` are escaped with a backslash.
+fn parse_csv_attributes(s: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    if s.is_empty() {
+        return attributes;
+    }
+    let (mut key, mut value) = (String::new(), String::new());
+    let mut in_value = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let current = if in_value { &mut value } else { &mut key };
+        match c {
+            '\\' => current.extend(chars.next()),
+            '=' if !in_value => in_value = true,
+            ';' => {
+                attributes.push((std::mem::take(&mut key), std::mem::take(&mut value)));
+                in_value = false;
+            }
+            c => current.push(c),
+        }
+    }
+    attributes.push((key, value));
+    attributes
+}
+
+/// A line of the JSON Lines format.
+#[derive(Deserialize)]
+struct JsonPoint {
+    timestamp: serde_json::Value,
+    metric: String,
+    value_type: String,
+    unit: String,
+    value: serde_json::Value,
+    resource_kind: String,
+    resource_id: String,
+    consumer_kind: String,
+    consumer_id: String,
+    attributes: HashMap<String, String>,
+}
+
+/// Parses a recording in the JSON Lines format.
+pub fn parse_json_lines(content: &str) -> anyhow::Result<Vec<RecordedPoint>> {
+    let mut points = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parse_line = || -> anyhow::Result<RecordedPoint> {
+            let p: JsonPoint = serde_json::from_str(line)?;
+            let timestamp = match &p.timestamp {
+                serde_json::Value::String(s) => parse_timestamp(s)?,
+                serde_json::Value::Number(n) => parse_timestamp(&n.to_string())?,
+                t => return Err(anyhow!("invalid timestamp {t}")),
+            };
+            let value = match (p.value_type.as_str(), &p.value) {
+                ("u64", v) => WrappedMeasurementValue::U64(v.as_u64().with_context(|| format!("invalid u64 {v}"))?),
+                // NaN and infinite numbers are written as null.
+                ("f64", serde_json::Value::Null) => WrappedMeasurementValue::F64(f64::NAN),
+                ("f64", v) => WrappedMeasurementValue::F64(v.as_f64().with_context(|| format!("invalid f64 {v}"))?),
+                (t, _) => return Err(anyhow!("invalid value type {t}")),
+            };
+            let mut attributes: Vec<(String, String)> = p.attributes.into_iter().collect();
+            attributes.sort();
+            Ok(RecordedPoint {
+                timestamp,
+                metric: p.metric,
+                value,
+                resource: Resource::parse(p.resource_kind, p.resource_id)?,
+                consumer: ResourceConsumer::parse(p.consumer_kind, p.consumer_id)?,
+                attributes,
+                unit: Some(parse_unit(&p.unit)),
+            })
+        };
+        points.push(parse_line().with_context(|| format!("line {}", i + 1))?);
+    }
+    Ok(points)
+}
+
+/// Parses a timestamp written as a RFC 3339 string, or as a number of nanoseconds since the UNIX epoch.
+fn parse_timestamp(s: &str) -> anyhow::Result<SystemTime> {
+    match s.parse::<i128>() {
+        Ok(nanos) => {
+            let abs = Duration::from_nanos(u64::try_from(nanos.unsigned_abs()).context("timestamp out of range")?);
+            Ok(if nanos >= 0 { UNIX_EPOCH + abs } else { UNIX_EPOCH - abs })
+        }
+        Err(_) => humantime::parse_rfc3339(s).with_context(|| format!("invalid timestamp {s}")),
+    }
+}
+
+/// Parses a value that has been written without its type: integers become `u64`, other numbers become `f64`.
+fn parse_value(s: &str) -> anyhow::Result<WrappedMeasurementValue> {
+    if let Ok(x) = s.parse::<u64>() {
+        return Ok(WrappedMeasurementValue::U64(x));
+    }
+    let x: f64 = s.parse().with_context(|| format!("invalid value {s}"))?;
+    Ok(WrappedMeasurementValue::F64(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use alumet::measurement::MeasurementPoint;
+    use alumet::metrics::Metric;
+
+    use crate::output::jsonl::JsonLinesOptions;
+
+    use super::*;
+
+    /// Points of a counter, recorded every second.
+    fn recorded_points(n: u64) -> Vec<RecordedPoint> {
+        (0..n)
+            .map(|i| RecordedPoint {
+                timestamp: UNIX_EPOCH + Duration::from_secs(1000 + i),
+                metric: String::from("counter"),
+                value: WrappedMeasurementValue::U64(i),
+                resource: Resource::LocalMachine,
+                consumer: ResourceConsumer::LocalMachine,
+                attributes: vec![],
+                unit: None,
+            })
+            .collect()
+    }
+
+    fn replay(pace: ReplayPace) -> ReplaySource {
+        let metrics = HashMap::from([(
+            String::from("counter"),
+            (RawMetricId::from_u64(0), WrappedMeasurementType::U64),
+        )]);
+        ReplaySource::new(recorded_points(4), metrics, pace).unwrap()
+    }
+
+    /// Returns the values of the points replayed `elapsed_ms` after the beginning of the replay.
+    fn replayed_values(source: &mut ReplaySource, elapsed_ms: u64) -> Vec<u64> {
+        source
+            .next_points(Duration::from_millis(elapsed_ms))
+            .into_iter()
+            .map(|p| match p.value {
+                WrappedMeasurementValue::U64(x) => x,
+                WrappedMeasurementValue::F64(x) => panic!("unexpected float {x}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay_at_the_original_pace() {
+        let mut source = replay(ReplayPace::Original);
+        assert_eq!(replayed_values(&mut source, 0), [0]);
+        assert_eq!(replayed_values(&mut source, 999), [0u64; 0]);
+        assert_eq!(replayed_values(&mut source, 1500), [1]);
+        assert_eq!(replayed_values(&mut source, 3000), [2, 3]);
+        assert_eq!(replayed_values(&mut source, 10_000), [0u64; 0]);
+    }
+
+    #[test]
+    fn replay_faster() {
+        let mut source = replay(ReplayPace::Accelerated { factor: 2.0 });
+        assert_eq!(replayed_values(&mut source, 0), [0]);
+        assert_eq!(replayed_values(&mut source, 500), [1]);
+        assert_eq!(replayed_values(&mut source, 1499), [2]);
+        assert_eq!(replayed_values(&mut source, 1500), [3]);
+
+        let metrics = HashMap::new();
+        assert!(ReplaySource::new(vec![], metrics, ReplayPace::Accelerated { factor: 0.0 }).is_err());
+    }
+
+    #[test]
+    fn replay_as_fast_as_possible() {
+        let mut source = replay(ReplayPace::AsFastAsPossible { max_points_per_poll: 3 });
+        // The timestamps do not matter, only the number of points per poll.
+        assert_eq!(replayed_values(&mut source, 0), [0, 1, 2]);
+        assert_eq!(replayed_values(&mut source, 0), [3]);
+        assert_eq!(replayed_values(&mut source, 0), [0u64; 0]);
+    }
+
+    #[test]
+    fn replayed_points_are_flagged() {
+        let mut source = replay(ReplayPace::AsFastAsPossible { max_points_per_poll: 1 });
+        let points = source.next_points(Duration::ZERO);
+        let attributes: Vec<(&str, String)> = points[0].attributes().map(|(k, v)| (k, v.to_string())).collect();
+        assert_eq!(attributes, [("replayed", String::from("true"))]);
+        assert_eq!(
+            SystemTime::from(points[0].timestamp),
+            UNIX_EPOCH + Duration::from_secs(1000)
+        );
+    }
+
+    #[test]
+    fn parse_units() {
+        assert_eq!(parse_unit("1"), Unit::Unity.into());
+        assert_eq!(parse_unit("J"), Unit::Joule.into());
+        assert_eq!(parse_unit("milliW"), PrefixedUnit::milli(Unit::Watt));
+        assert_eq!(parse_unit("kiloBy"), PrefixedUnit::kilo(Unit::Byte));
+        // Not a prefix followed by a standard unit.
+        let custom = |name: &str| -> PrefixedUnit {
+            Unit::Custom {
+                unique_name: name.to_owned(),
+                display_name: name.to_owned(),
+            }
+            .into()
+        };
+        assert_eq!(parse_unit("percent"), custom("percent"));
+        assert_eq!(parse_unit("millijiffy"), custom("millijiffy"));
+    }
+
+    #[test]
+    fn json_lines_keep_the_unit() {
+        let metric = Metric {
+            name: String::from("power"),
+            description: String::new(),
+            value_type: WrappedMeasurementType::F64,
+            unit: PrefixedUnit::milli(Unit::Watt),
+        };
+        let point = MeasurementPoint::new_untyped(
+            Timestamp::from(UNIX_EPOCH + Duration::from_secs(1)),
+            RawMetricId::from_u64(0),
+            Resource::CpuPackage { id: 0 },
+            ResourceConsumer::LocalMachine,
+            WrappedMeasurementValue::F64(1.5),
+        );
+        let line = JsonLinesOptions::default().record(&point, &metric).unwrap();
+
+        let points = parse_json_lines(&line).unwrap();
+        assert_eq!(points[0].unit, Some(PrefixedUnit::milli(Unit::Watt)));
+        let metrics = recorded_metrics(&points).unwrap();
+        assert_eq!(
+            metrics,
+            vec![RecordedMetric {
+                name: String::from("power"),
+                value_type: WrappedMeasurementType::F64,
+                unit: PrefixedUnit::milli(Unit::Watt),
+            }]
+        );
+    }
+
+    #[test]
+    fn text_has_no_unit() {
+        let content = "SystemTime { tv_sec: 1, tv_nsec: 0 }: counter = 1; resource = local_machine/; consumer = local_machine/; attributes = []\n\
+                       SystemTime { tv_sec: 2, tv_nsec: 0 }: counter = 2.5; resource = local_machine/; consumer = local_machine/; attributes = []\n";
+        let metrics = recorded_metrics(&parse_text(content).unwrap()).unwrap();
+        assert_eq!(
+            metrics,
+            vec![RecordedMetric {
+                name: String::from("counter"),
+                value_type: WrappedMeasurementType::F64,
+                unit: Unit::Unity.into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn reject_a_metric_with_two_units() {
+        let line = |unit: &str| {
+            format!(
+                r#"{{"timestamp":1,"metric":"energy","unit":"{unit}","value_type":"f64","value":1.0,"resource_kind":"local_machine","resource_id":"","consumer_kind":"local_machine","consumer_id":"","attributes":{{}}}}"#
+            )
+        };
+        let content = format!("{}\n{}\n", line("J"), line("milliJ"));
+        let points = parse_json_lines(&content).unwrap();
+        let err = recorded_metrics(&points).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "metric energy is recorded with two units: J and milliJ"
+        );
+    }
+}
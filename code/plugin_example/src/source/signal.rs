@@ -0,0 +1,407 @@
+//! Source that generates synthetic signals: sine, square, ramp, noise and step.
+//!
+//! The signals are deterministic: the value of a signal only depends on its definition (including its seed)
+//! and on the number of times that the source has been polled, not on the wall-clock time.
+//! Two runs with the same config produce the same values, bit for bit, on the same platform.
+
+use std::collections::HashSet;
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use alumet::measurement::{
+    MeasurementAccumulator, MeasurementPoint, Timestamp, WrappedMeasurementType, WrappedMeasurementValue,
+};
+use alumet::metrics::RawMetricId;
+use alumet::pipeline::elements::error::PollError;
+use alumet::pipeline::Source;
+use alumet::resources::{Resource, ResourceConsumer};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use super::ResourceSpec;
+
+/// Configuration of the synthetic signals.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalConfig {
+    /// If true, the signals are generated.
+    pub enabled: bool,
+    /// Time between two samples of the signals.
+    #[serde(with = "humantime_serde")]
+    pub poll_interval: Duration,
+    /// The signals to generate. Each signal has its own metric.
+    pub signals: Vec<SignalDefinition>,
+}
+
+/// Definition of a synthetic signal.
+///
+/// The generated value is `offset + amplitude * waveform + noise * uniform(-1, 1)`,
+/// where the waveform is between -1 and 1 (between 0 and 1 for the step).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalDefinition {
+    /// Name of the metric of the signal.
+    pub name: String,
+    /// Type of the values. The `u64` values are rounded, and the negative values become 0.
+    pub value_type: SignalType,
+    pub waveform: Waveform,
+    pub amplitude: f64,
+    pub offset: f64,
+    /// Amplitude of the uniform noise that is added to the waveform, 0 to disable it.
+    pub noise: f64,
+    /// Seed of the random number generator, used by the noise.
+    pub seed: u64,
+    pub resource: ResourceSpec,
+    pub consumer: ResourceSpec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalType {
+    F64,
+    U64,
+}
+
+/// Shape of a signal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Waveform {
+    Sine {
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+    /// 1 during the first part of the period (given by `duty_cycle`, between 0 and 1), -1 during the rest.
+    Square {
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+        duty_cycle: f64,
+    },
+    /// Sawtooth that goes from -1 to 1 during each period.
+    Ramp {
+        #[serde(with = "humantime_serde")]
+        period: Duration,
+    },
+    /// Uniform random values between -1 and 1.
+    Noise,
+    /// 0 until `at`, then 1.
+    Step {
+        #[serde(with = "humantime_serde")]
+        at: Duration,
+    },
+}
+
+impl Default for SignalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            poll_interval: Duration::from_secs(1),
+            signals: vec![SignalDefinition {
+                name: String::from("example_signal_sine"),
+                value_type: SignalType::F64,
+                waveform: Waveform::Sine {
+                    period: Duration::from_secs(60),
+                },
+                amplitude: 10.0,
+                offset: 20.0,
+                noise: 0.5,
+                seed: 42,
+                resource: ResourceSpec::local_machine(),
+                consumer: ResourceSpec::local_machine(),
+            }],
+        }
+    }
+}
+
+impl SignalConfig {
+    /// Checks the config, and returns an error that explains the problem if it is invalid.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.poll_interval.is_zero() {
+            return Err(anyhow!("the poll_interval of the signals must not be zero"));
+        }
+        let mut names = HashSet::new();
+        for s in &self.signals {
+            if !names.insert(&s.name) {
+                return Err(anyhow!("duplicate signal name: {}", s.name));
+            }
+            if !(s.amplitude.is_finite() && s.offset.is_finite() && s.noise.is_finite()) {
+                return Err(anyhow!(
+                    "signal {}: amplitude, offset and noise must be finite numbers",
+                    s.name
+                ));
+            }
+            match &s.waveform {
+                Waveform::Sine { period } | Waveform::Ramp { period } | Waveform::Square { period, .. }
+                    if period.is_zero() =>
+                {
+                    return Err(anyhow!("signal {}: the period must not be zero", s.name));
+                }
+                Waveform::Square { duty_cycle, .. } if !(0.0..=1.0).contains(duty_cycle) => {
+                    return Err(anyhow!("signal {}: the duty_cycle must be between 0 and 1", s.name));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A signal that is ready to be sampled.
+pub struct Signal {
+    definition: SignalDefinition,
+    metric: RawMetricId,
+    resource: Resource,
+    consumer: ResourceConsumer,
+    rng: SplitMix64,
+}
+
+impl Signal {
+    /// Prepares a signal. Its values are measured by `metric`, which must have the type of the signal.
+    pub fn new(definition: SignalDefinition, metric: RawMetricId) -> anyhow::Result<Self> {
+        Ok(Self {
+            resource: definition.resource.to_resource()?,
+            consumer: definition.consumer.to_consumer()?,
+            rng: SplitMix64(definition.seed),
+            definition,
+            metric,
+        })
+    }
+
+    /// Returns the value of the signal at `elapsed`, the (theoretical) time since the first sample, in nanoseconds.
+    ///
+    /// Every call consumes random numbers, hence the samples must be requested in order.
+    pub fn sample(&mut self, elapsed: u128) -> WrappedMeasurementValue {
+        let def = &self.definition;
+        let shape = match &def.waveform {
+            Waveform::Sine { period } => (TAU * phase(elapsed, *period)).sin(),
+            Waveform::Square { period, duty_cycle } => {
+                if phase(elapsed, *period) < *duty_cycle {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Ramp { period } => 2.0 * phase(elapsed, *period) - 1.0,
+            Waveform::Noise => 2.0 * self.rng.next_f64() - 1.0,
+            Waveform::Step { at } => {
+                if elapsed < at.as_nanos() {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        };
+        let noise = 2.0 * self.rng.next_f64() - 1.0;
+        let value = def.offset + def.amplitude * shape + def.noise * noise;
+        match def.value_type {
+            SignalType::F64 => WrappedMeasurementValue::F64(value),
+            // `as` saturates: negative values become 0.
+            SignalType::U64 => WrappedMeasurementValue::U64(value.round() as u64),
+        }
+    }
+}
+
+impl SignalType {
+    pub fn wrapped(self) -> WrappedMeasurementType {
+        match self {
+            SignalType::F64 => WrappedMeasurementType::F64,
+            SignalType::U64 => WrappedMeasurementType::U64,
+        }
+    }
+}
+
+/// Returns the position of `elapsed` (in nanoseconds) in the current period, between 0 (included) and 1 (excluded).
+fn phase(elapsed: u128, period: Duration) -> f64 {
+    // Integer arithmetic, to avoid accumulating rounding errors.
+    let nanos = elapsed % period.as_nanos();
+    nanos as f64 / period.as_nanos() as f64
+}
+
+/// Produces samples of synthetic signals.
+pub struct SignalSource {
+    signals: Vec<Signal>,
+    poll_interval: Duration,
+    n_polls: u64,
+}
+
+impl SignalSource {
+    pub fn new(signals: Vec<Signal>, poll_interval: Duration) -> Self {
+        Self {
+            signals,
+            poll_interval,
+            n_polls: 0,
+        }
+    }
+}
+
+impl Source for SignalSource {
+    fn poll(&mut self, acc: &mut MeasurementAccumulator, timestamp: Timestamp) -> Result<(), PollError> {
+        // The time of the sample is computed from the number of polls, for the signals to be reproducible.
+        // In nanoseconds, a u128 cannot overflow, unlike a Duration.
+        let elapsed = self.poll_interval.as_nanos() * u128::from(self.n_polls);
+        self.n_polls += 1;
+        for signal in &mut self.signals {
+            let value = signal.sample(elapsed);
+            let point = MeasurementPoint::new_untyped(
+                timestamp,
+                signal.metric,
+                signal.resource.clone(),
+                signal.consumer.clone(),
+                value,
+            );
+            acc.push(point);
+        }
+        Ok(())
+    }
+}
+
+/// Pseudo-random number generator [SplitMix64](https://prng.di.unimi.it/splitmix64.c).
+///
+/// It is implemented here, instead of using a crate, to guarantee that its output never changes.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number between 0 (included) and 1 (excluded).
+    fn next_f64(&mut self) -> f64 {
+        // 53 random bits, the precision of a f64.
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u128 = 1_000_000_000;
+
+    fn signal(waveform: Waveform, value_type: SignalType, noise: f64, seed: u64) -> Signal {
+        let definition = SignalDefinition {
+            name: String::from("test"),
+            value_type,
+            waveform,
+            amplitude: 1.0,
+            offset: 0.0,
+            noise,
+            seed,
+            resource: ResourceSpec::local_machine(),
+            consumer: ResourceSpec::local_machine(),
+        };
+        Signal::new(definition, RawMetricId::from_u64(0)).unwrap()
+    }
+
+    /// Samples the signal at the given times, in seconds.
+    fn samples(signal: &mut Signal, times: &[f64]) -> Vec<f64> {
+        times
+            .iter()
+            .map(|t| match signal.sample((t * SECOND as f64) as u128) {
+                WrappedMeasurementValue::F64(x) => x,
+                WrappedMeasurementValue::U64(x) => x as f64,
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: Vec<f64>, expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{actual:?} != {expected:?}");
+        }
+    }
+
+    fn bits(signal: &mut Signal) -> Vec<u64> {
+        (0..100)
+            .map(|i| match signal.sample(i * SECOND) {
+                WrappedMeasurementValue::F64(x) => x.to_bits(),
+                WrappedMeasurementValue::U64(x) => x,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_values() {
+        let sine = || Waveform::Sine {
+            period: Duration::from_secs(7),
+        };
+        let a = bits(&mut signal(sine(), SignalType::F64, 0.5, 42));
+        let b = bits(&mut signal(sine(), SignalType::F64, 0.5, 42));
+        assert_eq!(a, b);
+        let c = bits(&mut signal(sine(), SignalType::F64, 0.5, 43));
+        assert_ne!(a, c);
+        let noise = bits(&mut signal(Waveform::Noise, SignalType::F64, 0.0, 42));
+        assert_ne!(noise, bits(&mut signal(Waveform::Noise, SignalType::F64, 0.0, 43)));
+    }
+
+    #[test]
+    fn random_numbers_never_change() {
+        // Reference values of SplitMix64 with the seed 1234567.
+        let mut rng = SplitMix64(1234567);
+        let values: Vec<u64> = (0..5).map(|_| rng.next_u64()).collect();
+        assert_eq!(
+            values,
+            [
+                6457827717110365317,
+                3203168211198807973,
+                9817491932198370423,
+                4593380528125082431,
+                16408922859458223821
+            ]
+        );
+    }
+
+    #[test]
+    fn waveforms_at_known_phases() {
+        let period = Duration::from_secs(4);
+        let times = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+
+        let mut sine = signal(Waveform::Sine { period }, SignalType::F64, 0.0, 0);
+        assert_close(samples(&mut sine, &times), &[0.0, 1.0, 0.0, -1.0, 0.0, 1.0]);
+
+        let mut ramp = signal(Waveform::Ramp { period }, SignalType::F64, 0.0, 0);
+        assert_close(samples(&mut ramp, &times), &[-1.0, -0.5, 0.0, 0.5, -1.0, -0.5]);
+
+        let square = Waveform::Square {
+            period,
+            duty_cycle: 0.25,
+        };
+        let mut square = signal(square, SignalType::F64, 0.0, 0);
+        assert_close(
+            samples(&mut square, &[0.0, 0.5, 1.0, 3.5, 4.0]),
+            &[1.0, 1.0, -1.0, -1.0, 1.0],
+        );
+
+        let step = Waveform::Step {
+            at: Duration::from_secs(2),
+        };
+        let mut step = signal(step, SignalType::F64, 0.0, 0);
+        assert_close(samples(&mut step, &[0.0, 1.9, 2.0, 100.0]), &[0.0, 0.0, 1.0, 1.0]);
+
+        let mut noise = signal(Waveform::Noise, SignalType::F64, 0.0, 0);
+        assert!(samples(&mut noise, &[0.0; 100]).iter().all(|x| (-1.0..1.0).contains(x)));
+    }
+
+    #[test]
+    fn u64_values_are_rounded_and_clamped() {
+        let step = || Waveform::Step { at: Duration::ZERO };
+        let sample = |amplitude: f64| {
+            let mut s = signal(step(), SignalType::U64, 0.0, 0);
+            s.definition.amplitude = amplitude;
+            s.sample(0)
+        };
+        assert_eq!(sample(2.4), WrappedMeasurementValue::U64(2));
+        assert_eq!(sample(2.5), WrappedMeasurementValue::U64(3));
+        assert_eq!(sample(-3.0), WrappedMeasurementValue::U64(0));
+        assert_eq!(sample(1e30), WrappedMeasurementValue::U64(u64::MAX));
+    }
+
+    #[test]
+    fn phase_after_a_long_time() {
+        // More nanoseconds than a u64 can hold.
+        let period = Duration::from_secs(3);
+        let elapsed = u128::from(u64::MAX) * 3 * SECOND + SECOND;
+        assert!((phase(elapsed, period) - 1.0 / 3.0).abs() < 1e-9);
+    }
+}